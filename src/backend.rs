@@ -0,0 +1,567 @@
+/* -------------------------------------------------------------------------- */
+/*                                 backend.rs                                 */
+/* -------------------------------------------------------------------------- */
+
+use std::collections::HashMap;
+use std::fmt;
+use std::time::Duration;
+
+use anyhow::{Context, Result, anyhow, bail};
+use async_openai::types::ChatCompletionRequestMessage;
+use eventsource_stream::EventStreamError;
+use futures_util::stream::BoxStream;
+use serde::{Deserialize, Serialize};
+
+use crate::config::Config;
+use crate::models::is_reasoning_model;
+use crate::retry::{MAX_RETRY_AFTER, seconds_to_duration};
+
+mod anthropic;
+mod openai;
+
+pub use anthropic::AnthropicBackend;
+pub use openai::OpenAIBackend;
+
+/// A provider-agnostic chat request.
+#[derive(Debug, Clone)]
+pub struct ChatRequest {
+    pub model: String,
+    pub max_tokens: u32,
+    pub messages: Vec<ChatCompletionRequestMessage>,
+    /// Whether the model is a reasoning model, which changes the supported parameters.
+    pub reasoning: bool,
+    pub reasoning_effort: Option<String>,
+    pub sampling: Sampling,
+}
+
+impl ChatRequest {
+    pub fn with_reasoning_effort(mut self, reasoning_effort: Option<String>) -> Self {
+        self.reasoning_effort = reasoning_effort;
+        self
+    }
+
+    pub fn with_sampling(mut self, sampling: Sampling) -> Self {
+        self.sampling = sampling;
+        self
+    }
+}
+
+/// Optional sampling parameters. Unset values are left to the provider's
+/// defaults, and parameters a provider or model doesn't support are skipped.
+#[derive(Serialize, Deserialize, Default, Debug, Clone, PartialEq)]
+pub struct Sampling {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub temperature: Option<f32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub top_p: Option<f32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub presence_penalty: Option<f32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub frequency_penalty: Option<f32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub seed: Option<i64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub stop: Option<Vec<String>>,
+}
+
+impl Sampling {
+    pub const PARAMETERS: [&str; 6] = [
+        "temperature",
+        "top_p",
+        "presence_penalty",
+        "frequency_penalty",
+        "seed",
+        "stop",
+    ];
+
+    /// Returns a copy with any values set in `overrides` replacing these.
+    pub fn merged(&self, overrides: &Sampling) -> Sampling {
+        Sampling {
+            temperature: overrides.temperature.or(self.temperature),
+            top_p: overrides.top_p.or(self.top_p),
+            presence_penalty: overrides.presence_penalty.or(self.presence_penalty),
+            frequency_penalty: overrides.frequency_penalty.or(self.frequency_penalty),
+            seed: overrides.seed.or(self.seed),
+            stop: overrides.stop.clone().or_else(|| self.stop.clone()),
+        }
+    }
+
+    /// Sets a parameter by name from its string values. No values (or
+    /// "default") resets the parameter to the provider default.
+    pub fn set(&mut self, name: &str, values: &[String]) -> Result<()> {
+        let reset = values.is_empty() || values == ["default"];
+        let value = values.join(" ");
+        let mut updated = self.clone();
+        match name {
+            "temperature" => updated.temperature = parse_value(&value, reset)?,
+            "top_p" => updated.top_p = parse_value(&value, reset)?,
+            "presence_penalty" => updated.presence_penalty = parse_value(&value, reset)?,
+            "frequency_penalty" => updated.frequency_penalty = parse_value(&value, reset)?,
+            "seed" => updated.seed = parse_value(&value, reset)?,
+            // each argument is a separate stop sequence
+            "stop" => updated.stop = (!reset).then(|| values.to_vec()),
+            other => bail!(
+                "Unknown parameter '{}' (expected one of: {})",
+                other,
+                Self::PARAMETERS.join(", ")
+            ),
+        }
+        updated.validate()?;
+        *self = updated;
+        Ok(())
+    }
+
+    /// Checks that every set value is within the range accepted by the APIs.
+    pub fn validate(&self) -> Result<()> {
+        check_range("temperature", self.temperature, 0.0, 2.0)?;
+        check_range("top_p", self.top_p, 0.0, 1.0)?;
+        check_range("presence_penalty", self.presence_penalty, -2.0, 2.0)?;
+        check_range("frequency_penalty", self.frequency_penalty, -2.0, 2.0)?;
+        if let Some(stop) = &self.stop
+            && stop.len() > 4
+        {
+            bail!("At most 4 stop sequences are supported");
+        }
+        Ok(())
+    }
+
+    /// The current value of each parameter, or `None` when unset.
+    pub fn values(&self) -> Vec<(&'static str, Option<String>)> {
+        vec![
+            ("temperature", self.temperature.map(|v| v.to_string())),
+            ("top_p", self.top_p.map(|v| v.to_string())),
+            (
+                "presence_penalty",
+                self.presence_penalty.map(|v| v.to_string()),
+            ),
+            (
+                "frequency_penalty",
+                self.frequency_penalty.map(|v| v.to_string()),
+            ),
+            ("seed", self.seed.map(|v| v.to_string())),
+            ("stop", self.stop.as_ref().map(|v| format!("{:?}", v))),
+        ]
+    }
+}
+
+fn parse_value<T: std::str::FromStr>(value: &str, reset: bool) -> Result<Option<T>> {
+    if reset {
+        return Ok(None);
+    }
+    match value.parse() {
+        Ok(parsed) => Ok(Some(parsed)),
+        Err(_) => bail!("Invalid value '{}'", value),
+    }
+}
+
+fn check_range(name: &str, value: Option<f32>, min: f32, max: f32) -> Result<()> {
+    match value {
+        Some(value) if !(min..=max).contains(&value) => {
+            bail!(
+                "{} must be between {} and {} (got {})",
+                name,
+                min,
+                max,
+                value
+            )
+        }
+        _ => Ok(()),
+    }
+}
+
+/// A single event produced while streaming a response.
+#[derive(Debug, Clone)]
+pub enum ChatEvent {
+    Text(String),
+    /// Reasoning summary or thinking text, shown separately from the answer.
+    Reasoning(String),
+    /// Token usage so far. Providers may report it in several parts, and each
+    /// count only grows, so events are combined with [`Usage::merge`].
+    Usage(Usage),
+}
+
+/// Tokens used by a response.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default, PartialEq)]
+pub struct Usage {
+    pub prompt_tokens: u32,
+    pub completion_tokens: u32,
+}
+
+impl Usage {
+    pub fn merge(&mut self, other: Usage) {
+        self.prompt_tokens = self.prompt_tokens.max(other.prompt_tokens);
+        self.completion_tokens = self.completion_tokens.max(other.completion_tokens);
+    }
+}
+
+pub type ChatStream = BoxStream<'static, Result<ChatEvent>>;
+
+pub trait ChatBackend {
+    /// Start a streaming completion for the request.
+    async fn stream_chat(&self, request: ChatRequest) -> Result<ChatStream>;
+
+    /// Run a non-streaming completion and return the full response text.
+    async fn complete(&self, request: ChatRequest) -> Result<String>;
+
+    /// List the model identifiers offered by the provider.
+    async fn list_models(&self) -> Result<Vec<String>>;
+}
+
+/// An error response from a provider's HTTP API.
+#[derive(Debug)]
+pub struct ApiError {
+    pub status: reqwest::StatusCode,
+    /// How long the server asked us to wait before retrying, if it said.
+    pub retry_after: Option<Duration>,
+    pub message: String,
+}
+
+impl fmt::Display for ApiError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} ({})", self.message, self.status)
+    }
+}
+
+impl std::error::Error for ApiError {}
+
+/// Turns a non-success response into an [`ApiError`], using the
+/// `{"error": {"message": ...}}` body that OpenAI and Anthropic both send.
+async fn check_status(response: reqwest::Response) -> Result<reqwest::Response> {
+    let status = response.status();
+    if status.is_success() {
+        return Ok(response);
+    }
+    let retry_after = parse_retry_after(response.headers());
+    let body = response.text().await.unwrap_or_default();
+    let message = match serde_json::from_str::<ErrorResponse>(&body) {
+        Ok(error) => error.error.message,
+        Err(_) if body.trim().is_empty() => String::from("API request failed"),
+        Err(_) => format!("API request failed: {}", body.trim()),
+    };
+    Err(ApiError {
+        status,
+        retry_after,
+        message,
+    }
+    .into())
+}
+
+fn parse_retry_after(headers: &reqwest::header::HeaderMap) -> Option<Duration> {
+    let header = |name| headers.get(name)?.to_str().ok()?.trim().parse::<f64>().ok();
+    // OpenAI also sends the more precise retry-after-ms
+    if let Some(millis) = header("retry-after-ms") {
+        return seconds_to_duration(millis / 1000.0, MAX_RETRY_AFTER);
+    }
+    // HTTP dates aren't supported and fall back to the backoff delay
+    header("retry-after").and_then(|secs| seconds_to_duration(secs, MAX_RETRY_AFTER))
+}
+
+/// Keeps transport errors as `reqwest::Error` so they can be retried.
+fn stream_error(err: EventStreamError<reqwest::Error>) -> anyhow::Error {
+    match err {
+        EventStreamError::Transport(err) => err.into(),
+        err => anyhow!("Invalid event stream: {}", err),
+    }
+}
+
+#[derive(Deserialize, Debug)]
+struct ErrorResponse {
+    error: ErrorBody,
+}
+
+#[derive(Deserialize, Debug)]
+struct ErrorBody {
+    message: String,
+}
+
+/// Builds the HTTP client shared by all requests to a provider, with the
+/// configured timeouts (0 disables one), proxy and extra headers. Header
+/// values may reference environment variables, e.g. `"Bearer ${GATEWAY_TOKEN}"`.
+pub fn http_client(config: &Config) -> Result<reqwest::Client> {
+    let mut builder = reqwest::Client::builder();
+    if config.connect_timeout > 0 {
+        builder = builder.connect_timeout(Duration::from_secs(config.connect_timeout));
+    }
+    if config.read_timeout > 0 {
+        builder = builder.read_timeout(Duration::from_secs(config.read_timeout));
+    }
+    if config.timeout > 0 {
+        builder = builder.timeout(Duration::from_secs(config.timeout));
+    }
+    if let Some(proxy) = &config.proxy {
+        let proxy =
+            reqwest::Proxy::all(proxy).with_context(|| format!("Invalid proxy URL '{}'", proxy))?;
+        builder = builder.proxy(proxy);
+    }
+    let mut headers = reqwest::header::HeaderMap::new();
+    for (name, value) in &config.headers {
+        let value = shellexpand::env(value)
+            .with_context(|| format!("Failed to expand header '{}'", name))?;
+        let name = reqwest::header::HeaderName::from_bytes(name.as_bytes())
+            .with_context(|| format!("Invalid header name '{}'", name))?;
+        let value = reqwest::header::HeaderValue::from_str(&value)
+            .with_context(|| format!("Invalid value for header '{}'", name))?;
+        headers.insert(name, value);
+    }
+    builder
+        .default_headers(headers)
+        .build()
+        .context("Failed to create HTTP client")
+}
+
+/// Resolves the API key for a provider from the configured key source.
+///
+/// Sources are tried in order: `api_key_path`, `api_key_cmd`, then the
+/// `api_key_env` environment variable (or the provider's default variable).
+pub fn resolve_api_key(config: &Config, default_env: &str) -> Result<String> {
+    if let Some(path) = &config.api_key_path {
+        let expanded = shellexpand::full(path)
+            .with_context(|| format!("Failed to expand API key path '{}'", path))?;
+        let api_key = std::fs::read_to_string(expanded.as_ref())
+            .with_context(|| format!("Failed to read API key from file '{}'", path))?;
+        let api_key = api_key.trim();
+        if api_key.is_empty() {
+            bail!("API key file '{}' is empty", path);
+        }
+        return Ok(api_key.to_string());
+    }
+
+    if let Some(cmd) = &config.api_key_cmd {
+        let output = if cfg!(target_os = "windows") {
+            std::process::Command::new("cmd").args(["/C", cmd]).output()
+        } else {
+            std::process::Command::new("sh").args(["-c", cmd]).output()
+        }
+        .with_context(|| format!("Failed to run API key command '{}'", cmd))?;
+        if !output.status.success() {
+            let stderr = String::from_utf8_lossy(&output.stderr);
+            match stderr.trim() {
+                "" => bail!("API key command '{}' failed ({})", cmd, output.status),
+                stderr => bail!(
+                    "API key command '{}' failed ({}): {}",
+                    cmd,
+                    output.status,
+                    stderr
+                ),
+            }
+        }
+        let stdout = String::from_utf8_lossy(&output.stdout);
+        let api_key = stdout.lines().next().unwrap_or_default().trim();
+        if api_key.is_empty() {
+            bail!("API key command '{}' produced no output", cmd);
+        }
+        return Ok(api_key.to_string());
+    }
+
+    let api_key_env = config.api_key_env.as_deref().unwrap_or(default_env);
+    match std::env::var(api_key_env) {
+        Ok(api_key) => Ok(api_key),
+        // local OpenAI-compatible servers (Ollama, llama.cpp, vLLM) usually ignore the key
+        Err(_) if !config.require_api_key => Ok(String::new()),
+        Err(e) => Err(e).with_context(|| {
+            format!(
+                "Please set the {} environment variable to your API key, \
+                 or set api_key_path or api_key_cmd in the config file.",
+                api_key_env
+            )
+        }),
+    }
+}
+
+/// A single chat provider implementation.
+pub enum Provider {
+    OpenAI(Box<OpenAIBackend>),
+    Anthropic(AnthropicBackend),
+}
+
+impl Provider {
+    fn from_config(name: &str, config: &Config) -> Result<Self> {
+        match name {
+            "openai" => Ok(Provider::OpenAI(Box::new(OpenAIBackend::from_config(
+                config,
+            )?))),
+            "anthropic" => Ok(Provider::Anthropic(AnthropicBackend::from_config(config)?)),
+            other => bail!("Unknown provider '{}'", other),
+        }
+    }
+}
+
+impl ChatBackend for Provider {
+    async fn stream_chat(&self, request: ChatRequest) -> Result<ChatStream> {
+        match self {
+            Provider::OpenAI(backend) => backend.stream_chat(request).await,
+            Provider::Anthropic(backend) => backend.stream_chat(request).await,
+        }
+    }
+
+    async fn complete(&self, request: ChatRequest) -> Result<String> {
+        match self {
+            Provider::OpenAI(backend) => backend.complete(request).await,
+            Provider::Anthropic(backend) => backend.complete(request).await,
+        }
+    }
+
+    async fn list_models(&self) -> Result<Vec<String>> {
+        match self {
+            Provider::OpenAI(backend) => backend.list_models().await,
+            Provider::Anthropic(backend) => backend.list_models().await,
+        }
+    }
+}
+
+/// Routes each request to the provider configured for its model.
+///
+/// The global `provider` must be usable at startup. Providers only referenced
+/// from `[models]` tables are created with their default settings, and a
+/// model whose table sets an API key source gets a provider of its own with
+/// that key. Any error (e.g. a missing API key) is reported when such a model
+/// is used.
+pub struct Backend {
+    default_provider: String,
+    cache_key: String,
+    aliases: HashMap<String, String>,
+    model_providers: HashMap<String, String>,
+    reasoning_overrides: HashMap<String, bool>,
+    providers: HashMap<String, Result<Provider, String>>,
+}
+
+impl Backend {
+    pub fn from_config(config: &Config) -> Result<Self> {
+        let default_provider = config.provider.to_lowercase();
+        let mut providers = HashMap::new();
+        providers.insert(
+            default_provider.clone(),
+            Ok(Provider::from_config(&default_provider, config)?),
+        );
+
+        let reasoning_overrides = config
+            .models
+            .iter()
+            .filter_map(|(model, model_config)| Some((model.clone(), model_config.reasoning?)))
+            .collect();
+
+        // secondary providers share the network settings, but not the headers,
+        // which are usually meant for the configured endpoint only
+        let mut secondary_config = Config::default();
+        secondary_config.connect_timeout = config.connect_timeout;
+        secondary_config.read_timeout = config.read_timeout;
+        secondary_config.timeout = config.timeout;
+        secondary_config.proxy = config.proxy.clone();
+
+        let mut model_providers = HashMap::new();
+        for (model, model_config) in &config.models {
+            let provider = model_config.provider.as_ref().map(|p| p.to_lowercase());
+            if !model_config.has_api_key_source() {
+                if let Some(provider) = provider {
+                    model_providers.insert(model.clone(), provider);
+                }
+                continue;
+            }
+            let provider = provider.unwrap_or_else(|| default_provider.clone());
+            let mut key_config = if provider == default_provider {
+                config.clone()
+            } else {
+                secondary_config.clone()
+            };
+            key_config.api_key_env = model_config.api_key_env.clone();
+            key_config.api_key_path = model_config.api_key_path.clone();
+            key_config.api_key_cmd = model_config.api_key_cmd.clone();
+            let name = format!("{} ({})", provider, model);
+            let result =
+                Provider::from_config(&provider, &key_config).map_err(|e| format!("{:#}", e));
+            providers.insert(name.clone(), result);
+            model_providers.insert(model.clone(), name);
+        }
+        for provider in model_providers.values() {
+            if !providers.contains_key(provider) {
+                let result = Provider::from_config(provider, &secondary_config)
+                    .map_err(|e| format!("{:#}", e));
+                providers.insert(provider.clone(), result);
+            }
+        }
+
+        let cache_key = format!(
+            "{}:{}",
+            default_provider,
+            config.api_base.as_deref().unwrap_or_default()
+        );
+
+        Ok(Backend {
+            default_provider,
+            cache_key,
+            aliases: config.aliases.clone(),
+            model_providers,
+            reasoning_overrides,
+            providers,
+        })
+    }
+
+    fn provider_for(&self, model: &str) -> Result<&Provider> {
+        let name = self
+            .model_providers
+            .get(model)
+            .unwrap_or(&self.default_provider);
+        match self.providers.get(name) {
+            Some(Ok(provider)) => Ok(provider),
+            Some(Err(e)) => Err(anyhow!("Provider '{}' is unavailable: {}", name, e)),
+            None => bail!("Unknown provider '{}'", name),
+        }
+    }
+
+    /// Identifies the default provider and endpoint, for caching its model list.
+    pub fn cache_key(&self) -> &str {
+        &self.cache_key
+    }
+
+    fn apply_overrides(&self, mut request: ChatRequest) -> ChatRequest {
+        if let Some(model) = self.aliases.get(&request.model) {
+            request.reasoning = is_reasoning_model(model);
+            request.model = model.clone();
+        }
+        if let Some(reasoning) = self.reasoning_overrides.get(&request.model) {
+            request.reasoning = *reasoning;
+        }
+        request
+    }
+}
+
+impl ChatBackend for Backend {
+    async fn stream_chat(&self, request: ChatRequest) -> Result<ChatStream> {
+        let request = self.apply_overrides(request);
+        self.provider_for(&request.model)?
+            .stream_chat(request)
+            .await
+    }
+
+    async fn complete(&self, request: ChatRequest) -> Result<String> {
+        let request = self.apply_overrides(request);
+        self.provider_for(&request.model)?.complete(request).await
+    }
+
+    async fn list_models(&self) -> Result<Vec<String>> {
+        let mut models = Vec::new();
+        let mut last_error = None;
+        for provider in self.providers.values().filter_map(|p| p.as_ref().ok()) {
+            match provider.list_models().await {
+                // a provider can be listed again for models with their own key
+                Ok(provider_models) => {
+                    for model in provider_models {
+                        if !models.contains(&model) {
+                            models.push(model);
+                        }
+                    }
+                }
+                Err(e) => last_error = Some(e),
+            }
+        }
+        if let Some(e) = last_error.filter(|_| models.is_empty()) {
+            return Err(e);
+        }
+        for model in self.model_providers.keys() {
+            if !models.contains(model) {
+                models.push(model.clone());
+            }
+        }
+        Ok(models)
+    }
+}
@@ -0,0 +1,360 @@
+/* -------------------------------------------------------------------------- */
+/*                            backend/anthropic.rs                            */
+/* -------------------------------------------------------------------------- */
+
+use anyhow::{Result, anyhow, bail};
+use async_openai::types::ChatCompletionRequestMessage;
+use eventsource_stream::Eventsource;
+use futures_util::stream::StreamExt;
+use serde::{Deserialize, Serialize};
+
+use super::{
+    ApiError, ChatBackend, ChatEvent, ChatRequest, ChatStream, Usage, check_status, http_client,
+    resolve_api_key, stream_error,
+};
+use crate::config::Config;
+use crate::utils::message_text;
+
+const DEFAULT_API_BASE: &str = "https://api.anthropic.com/v1";
+const ANTHROPIC_VERSION: &str = "2023-06-01";
+
+pub struct AnthropicBackend {
+    http: reqwest::Client,
+    api_base: String,
+    api_key: String,
+}
+
+impl AnthropicBackend {
+    pub fn new(api_key: String, api_base: String, http: reqwest::Client) -> Self {
+        AnthropicBackend {
+            http,
+            api_base,
+            api_key,
+        }
+    }
+
+    pub fn from_config(config: &Config) -> Result<Self> {
+        let api_key = resolve_api_key(config, "ANTHROPIC_API_KEY")?;
+        let api_base = config
+            .api_base
+            .as_deref()
+            .unwrap_or(DEFAULT_API_BASE)
+            .trim_end_matches('/')
+            .to_string();
+        Ok(AnthropicBackend::new(
+            api_key,
+            api_base,
+            http_client(config)?,
+        ))
+    }
+
+    fn build_request(&self, request: ChatRequest, stream: bool) -> Result<MessagesRequest> {
+        let mut system = Vec::new();
+        let mut messages: Vec<Message> = Vec::new();
+        for message in &request.messages {
+            let role = match message {
+                ChatCompletionRequestMessage::System(_)
+                | ChatCompletionRequestMessage::Developer(_) => {
+                    system.push(message_text(message).to_string());
+                    continue;
+                }
+                ChatCompletionRequestMessage::User(_) => "user",
+                ChatCompletionRequestMessage::Assistant(_) => "assistant",
+                _ => continue,
+            };
+            let text = message_text(message);
+            if text.is_empty() {
+                continue;
+            }
+            // the Messages API expects alternating roles, so merge consecutive turns
+            match messages.last_mut() {
+                Some(last) if last.role == role => last.content.push(ContentBlock::text(text)),
+                _ => messages.push(Message {
+                    role,
+                    content: vec![ContentBlock::text(text)],
+                }),
+            }
+        }
+        let system = system.join("\n\n");
+        let thinking = match &request.reasoning_effort {
+            Some(effort) if request.reasoning => {
+                thinking_budget(effort, request.max_tokens)?.map(|budget_tokens| Thinking {
+                    kind: "enabled",
+                    budget_tokens,
+                })
+            }
+            _ => None,
+        };
+        // extended thinking doesn't allow changing temperature or top_p, and the
+        // Messages API has no penalty or seed parameters
+        let sampling = request.sampling;
+        let (temperature, top_p) = match thinking {
+            Some(_) => (None, None),
+            None => (sampling.temperature, sampling.top_p),
+        };
+        Ok(MessagesRequest {
+            model: request.model,
+            max_tokens: request.max_tokens,
+            system: (!system.is_empty()).then_some(system),
+            messages,
+            thinking,
+            temperature,
+            top_p,
+            stop_sequences: sampling.stop,
+            stream,
+        })
+    }
+
+    async fn post_messages(&self, body: &MessagesRequest) -> Result<reqwest::Response> {
+        let response = self
+            .http
+            .post(format!("{}/messages", self.api_base))
+            .header("x-api-key", &self.api_key)
+            .header("anthropic-version", ANTHROPIC_VERSION)
+            .json(body)
+            .send()
+            .await?;
+        check_status(response).await
+    }
+}
+
+impl ChatBackend for AnthropicBackend {
+    async fn stream_chat(&self, request: ChatRequest) -> Result<ChatStream> {
+        let body = self.build_request(request, true)?;
+        let response = self.post_messages(&body).await?;
+        let events = response
+            .bytes_stream()
+            .eventsource()
+            .filter_map(|result| async move {
+                let event = match result {
+                    Ok(event) => event,
+                    Err(err) => return Some(Err(stream_error(err))),
+                };
+                match serde_json::from_str::<StreamEvent>(&event.data) {
+                    Ok(StreamEvent::ContentBlockDelta {
+                        delta: Delta::Text { text },
+                    }) => Some(Ok(ChatEvent::Text(text))),
+                    Ok(StreamEvent::ContentBlockDelta {
+                        delta: Delta::Thinking { thinking },
+                    }) => Some(Ok(ChatEvent::Reasoning(thinking))),
+                    // input tokens are reported when the message starts, and
+                    // the output tokens again when it ends
+                    Ok(StreamEvent::MessageStart { message }) => {
+                        Some(Ok(ChatEvent::Usage(message.usage.into())))
+                    }
+                    Ok(StreamEvent::MessageDelta { usage }) => {
+                        Some(Ok(ChatEvent::Usage(usage.into())))
+                    }
+                    Ok(StreamEvent::Error { error }) => Some(Err(error.into_api_error().into())),
+                    Ok(_) => None,
+                    Err(err) => Some(Err(anyhow!("Invalid stream event: {}", err))),
+                }
+            });
+        Ok(events.boxed())
+    }
+
+    async fn complete(&self, request: ChatRequest) -> Result<String> {
+        let body = self.build_request(request, false)?;
+        let response: MessagesResponse = self.post_messages(&body).await?.json().await?;
+        let content = response
+            .content
+            .into_iter()
+            .filter_map(|block| match block {
+                ResponseBlock::Text { text } => Some(text),
+                ResponseBlock::Other => None,
+            })
+            .collect::<String>();
+        Ok(content)
+    }
+
+    async fn list_models(&self) -> Result<Vec<String>> {
+        let response = self
+            .http
+            .get(format!("{}/models", self.api_base))
+            .header("x-api-key", &self.api_key)
+            .header("anthropic-version", ANTHROPIC_VERSION)
+            .query(&[("limit", "1000")])
+            .send()
+            .await?;
+        let models: ModelsResponse = check_status(response).await?.json().await?;
+        Ok(models.data.into_iter().map(|model| model.id).collect())
+    }
+}
+
+/// Maps a reasoning effort to an extended thinking budget. Thinking counts
+/// against `max_tokens`, so the budget is capped at half of it to leave room
+/// for the answer, and thinking is disabled if that is below the minimum of
+/// 1024 tokens.
+fn thinking_budget(effort: &str, max_tokens: u32) -> Result<Option<u32>> {
+    let budget: u32 = match effort.to_lowercase().as_str() {
+        "minimal" => 1024,
+        "low" => 2048,
+        "medium" => 8192,
+        "high" => 16384,
+        other => bail!(
+            "Invalid reasoning effort '{}' (expected minimal, low, medium or high)",
+            other
+        ),
+    };
+    let budget = budget.min(max_tokens / 2);
+    Ok((budget >= 1024).then_some(budget))
+}
+
+/* -------------------------------------------------------------------------- */
+/*                                 Wire types                                 */
+/* -------------------------------------------------------------------------- */
+
+#[derive(Serialize, Debug)]
+struct MessagesRequest {
+    model: String,
+    max_tokens: u32,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    system: Option<String>,
+    messages: Vec<Message>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    thinking: Option<Thinking>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    temperature: Option<f32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    top_p: Option<f32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    stop_sequences: Option<Vec<String>>,
+    stream: bool,
+}
+
+#[derive(Serialize, Debug)]
+struct Thinking {
+    #[serde(rename = "type")]
+    kind: &'static str,
+    budget_tokens: u32,
+}
+
+#[derive(Serialize, Debug)]
+struct Message {
+    role: &'static str,
+    content: Vec<ContentBlock>,
+}
+
+#[derive(Serialize, Debug)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum ContentBlock {
+    Text { text: String },
+}
+
+impl ContentBlock {
+    fn text(text: &str) -> Self {
+        ContentBlock::Text {
+            text: text.to_string(),
+        }
+    }
+}
+
+#[derive(Deserialize, Debug)]
+struct MessagesResponse {
+    content: Vec<ResponseBlock>,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum ResponseBlock {
+    Text {
+        text: String,
+    },
+    #[serde(other)]
+    Other,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum StreamEvent {
+    MessageStart {
+        message: StartedMessage,
+    },
+    ContentBlockDelta {
+        delta: Delta,
+    },
+    MessageDelta {
+        usage: MessageUsage,
+    },
+    Error {
+        error: StreamError,
+    },
+    #[serde(other)]
+    Other,
+}
+
+#[derive(Deserialize, Debug)]
+struct StartedMessage {
+    usage: MessageUsage,
+}
+
+#[derive(Deserialize, Debug)]
+struct MessageUsage {
+    #[serde(default)]
+    input_tokens: u32,
+    #[serde(default)]
+    cache_creation_input_tokens: Option<u32>,
+    #[serde(default)]
+    cache_read_input_tokens: Option<u32>,
+    #[serde(default)]
+    output_tokens: u32,
+}
+
+impl From<MessageUsage> for Usage {
+    fn from(usage: MessageUsage) -> Self {
+        // cached input is reported separately but is still part of the prompt
+        Usage {
+            prompt_tokens: usage.input_tokens
+                + usage.cache_creation_input_tokens.unwrap_or_default()
+                + usage.cache_read_input_tokens.unwrap_or_default(),
+            completion_tokens: usage.output_tokens,
+        }
+    }
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum Delta {
+    #[serde(rename = "text_delta")]
+    Text { text: String },
+    #[serde(rename = "thinking_delta")]
+    Thinking { thinking: String },
+    #[serde(other)]
+    Other,
+}
+
+#[derive(Deserialize, Debug)]
+struct StreamError {
+    #[serde(rename = "type")]
+    kind: String,
+    message: String,
+}
+
+impl StreamError {
+    /// Maps an error sent mid-stream to the status the same error has as a response.
+    fn into_api_error(self) -> ApiError {
+        let status = match self.kind.as_str() {
+            "overloaded_error" => 529,
+            "rate_limit_error" => 429,
+            "api_error" => 500,
+            "timeout_error" => 504,
+            _ => 400,
+        };
+        ApiError {
+            status: reqwest::StatusCode::from_u16(status)
+                .unwrap_or(reqwest::StatusCode::INTERNAL_SERVER_ERROR),
+            retry_after: None,
+            message: self.message,
+        }
+    }
+}
+
+#[derive(Deserialize, Debug)]
+struct ModelsResponse {
+    data: Vec<ModelInfo>,
+}
+
+#[derive(Deserialize, Debug)]
+struct ModelInfo {
+    id: String,
+}
@@ -0,0 +1,257 @@
+/* -------------------------------------------------------------------------- */
+/*                             backend/openai.rs                              */
+/* -------------------------------------------------------------------------- */
+
+use anyhow::{Result, anyhow, bail};
+use async_openai::Client;
+use async_openai::config::{Config as _, OpenAIConfig};
+use async_openai::types::{
+    ChatCompletionStreamOptions, CreateChatCompletionRequest, CreateChatCompletionRequestArgs,
+    ReasoningEffort,
+};
+use eventsource_stream::Eventsource;
+use futures_util::stream::{self, StreamExt};
+use serde::Deserialize;
+
+use super::{
+    ApiError, ChatBackend, ChatEvent, ChatRequest, ChatStream, Usage, check_status, http_client,
+    resolve_api_key, stream_error,
+};
+use crate::config::Config;
+
+pub struct OpenAIBackend {
+    client: Client<OpenAIConfig>,
+    // streaming goes through reqwest directly so that HTTP status codes and
+    // Retry-After headers are available when deciding whether to retry
+    http: reqwest::Client,
+    openai_config: OpenAIConfig,
+}
+
+impl OpenAIBackend {
+    pub fn new(openai_config: OpenAIConfig, http: reqwest::Client) -> Self {
+        OpenAIBackend {
+            client: Client::with_config(openai_config.clone()).with_http_client(http.clone()),
+            http,
+            openai_config,
+        }
+    }
+
+    pub fn from_config(config: &Config) -> Result<Self> {
+        let api_key = resolve_api_key(config, "OPENAI_API_KEY")?;
+        let mut openai_config = OpenAIConfig::new().with_api_key(api_key);
+        if let Some(api_base) = &config.api_base {
+            openai_config = openai_config.with_api_base(api_base.trim_end_matches('/'));
+        }
+        if let Some(organization) = &config.organization {
+            openai_config = openai_config.with_org_id(organization);
+        }
+        if let Some(project) = &config.project {
+            openai_config = openai_config.with_project_id(project);
+        }
+        Ok(OpenAIBackend::new(openai_config, http_client(config)?))
+    }
+
+    fn build_request(
+        &self,
+        request: ChatRequest,
+        stream: bool,
+    ) -> Result<CreateChatCompletionRequest> {
+        let mut args = CreateChatCompletionRequestArgs::default();
+        args.model(request.model)
+            .messages(request.messages)
+            .stream(stream);
+        if stream {
+            // the usage is sent in a final chunk without choices
+            args.stream_options(ChatCompletionStreamOptions {
+                include_usage: true,
+            });
+        }
+        let sampling = request.sampling;
+        if let Some(seed) = sampling.seed {
+            args.seed(seed);
+        }
+        if request.reasoning {
+            // reasoning models reject max_tokens in favour of max_completion_tokens,
+            // as well as the temperature, top_p, penalty and stop parameters
+            args.max_completion_tokens(request.max_tokens);
+            if let Some(effort) = &request.reasoning_effort {
+                args.reasoning_effort(parse_reasoning_effort(effort)?);
+            }
+        } else {
+            args.max_tokens(request.max_tokens);
+            if let Some(temperature) = sampling.temperature {
+                args.temperature(temperature);
+            }
+            if let Some(top_p) = sampling.top_p {
+                args.top_p(top_p);
+            }
+            if let Some(presence_penalty) = sampling.presence_penalty {
+                args.presence_penalty(presence_penalty);
+            }
+            if let Some(frequency_penalty) = sampling.frequency_penalty {
+                args.frequency_penalty(frequency_penalty);
+            }
+            if let Some(stop) = sampling.stop {
+                args.stop(stop);
+            }
+        }
+        Ok(args.build()?)
+    }
+}
+
+impl ChatBackend for OpenAIBackend {
+    async fn stream_chat(&self, request: ChatRequest) -> Result<ChatStream> {
+        let request = self.build_request(request, true)?;
+        let response = self
+            .http
+            .post(self.openai_config.url("/chat/completions"))
+            .headers(self.openai_config.headers())
+            .query(&self.openai_config.query())
+            .json(&request)
+            .send()
+            .await?;
+        let response = check_status(response).await?;
+        let events = response.bytes_stream().eventsource().flat_map(|result| {
+            let events = match result {
+                Ok(event) if event.data == "[DONE]" => Vec::new(),
+                Ok(event) => match serde_json::from_str::<StreamChunk>(&event.data) {
+                    Ok(chunk) => chunk.events(),
+                    Err(err) => vec![Err(anyhow!("Invalid stream chunk: {}", err))],
+                },
+                Err(err) => vec![Err(stream_error(err))],
+            };
+            stream::iter(events)
+        });
+        Ok(events.boxed())
+    }
+
+    async fn complete(&self, request: ChatRequest) -> Result<String> {
+        let request = self.build_request(request, false)?;
+        let response = self.client.chat().create(request).await?;
+        let content = response
+            .choices
+            .into_iter()
+            .next()
+            .and_then(|choice| choice.message.content)
+            .unwrap_or_default();
+        Ok(content)
+    }
+
+    async fn list_models(&self) -> Result<Vec<String>> {
+        let response = self.client.models().list().await?;
+        let mut models: Vec<String> = response
+            .data
+            .into_iter()
+            .map(|model| model.id)
+            .filter(|id| is_chat_model(id))
+            .collect();
+        models.sort();
+        Ok(models)
+    }
+}
+
+/// Filters out the embedding, audio and image models returned by `/models`.
+fn is_chat_model(id: &str) -> bool {
+    const NON_CHAT: [&str; 10] = [
+        "embedding",
+        "whisper",
+        "tts",
+        "dall-e",
+        "moderation",
+        "davinci",
+        "babbage",
+        "transcribe",
+        "image",
+        "realtime",
+    ];
+    !NON_CHAT.iter().any(|pattern| id.contains(pattern))
+}
+
+fn parse_reasoning_effort(effort: &str) -> Result<ReasoningEffort> {
+    match effort.to_lowercase().as_str() {
+        "minimal" => Ok(ReasoningEffort::Minimal),
+        "low" => Ok(ReasoningEffort::Low),
+        "medium" => Ok(ReasoningEffort::Medium),
+        "high" => Ok(ReasoningEffort::High),
+        other => bail!(
+            "Invalid reasoning effort '{}' (expected minimal, low, medium or high)",
+            other
+        ),
+    }
+}
+
+/* -------------------------------------------------------------------------- */
+/*                                 Wire types                                 */
+/* -------------------------------------------------------------------------- */
+
+// Stream chunks are parsed into our own types so that the `reasoning_content`
+// (DeepSeek, vLLM) and `reasoning` (Ollama, OpenRouter) fields sent by
+// OpenAI-compatible servers are not dropped.
+
+#[derive(Deserialize, Debug)]
+struct StreamChunk {
+    #[serde(default)]
+    choices: Vec<StreamChoice>,
+    /// Sent instead of choices when the server fails mid-stream.
+    error: Option<StreamError>,
+    usage: Option<StreamUsage>,
+}
+
+#[derive(Deserialize, Debug)]
+struct StreamUsage {
+    prompt_tokens: u32,
+    completion_tokens: u32,
+}
+
+#[derive(Deserialize, Debug)]
+struct StreamError {
+    message: String,
+}
+
+#[derive(Deserialize, Debug)]
+struct StreamChoice {
+    delta: StreamDelta,
+}
+
+#[derive(Deserialize, Debug, Default)]
+struct StreamDelta {
+    content: Option<String>,
+    reasoning_content: Option<String>,
+    reasoning: Option<String>,
+}
+
+impl StreamChunk {
+    fn events(self) -> Vec<Result<ChatEvent>> {
+        if let Some(error) = self.error {
+            // treated like a server error so that it can be retried
+            return vec![Err(ApiError {
+                status: reqwest::StatusCode::INTERNAL_SERVER_ERROR,
+                retry_after: None,
+                message: error.message,
+            }
+            .into())];
+        }
+        let mut events = Vec::new();
+        if let Some(usage) = self.usage {
+            events.push(Ok(ChatEvent::Usage(Usage {
+                prompt_tokens: usage.prompt_tokens,
+                completion_tokens: usage.completion_tokens,
+            })));
+        }
+        let Some(choice) = self.choices.into_iter().next() else {
+            return events;
+        };
+        let delta = choice.delta;
+        if let Some(reasoning) = delta.reasoning_content.or(delta.reasoning)
+            && !reasoning.is_empty()
+        {
+            events.push(Ok(ChatEvent::Reasoning(reasoning)));
+        }
+        if let Some(content) = delta.content
+            && !content.is_empty()
+        {
+            events.push(Ok(ChatEvent::Text(content)));
+        }
+        events
+    }
+}
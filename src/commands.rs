@@ -4,6 +4,8 @@
 
 use enum_iterator::Sequence;
 
+use crate::backend::Sampling;
+
 #[derive(Debug, Sequence, PartialEq, Clone, Copy)]
 pub enum Command {
     Exit,
@@ -11,9 +13,20 @@ pub enum Command {
     Reset,
     SelectModel,
     SelectTheme,
+    Profile,
+    Set,
+    Context,
+    Usage,
     Save,
     Load,
+    Export,
+    Search,
     History,
+    Retry,
+    Undo,
+    Edit,
+    Branches,
+    Switch,
     Help,
     Invalid,
 }
@@ -26,9 +39,20 @@ impl Command {
             Command::Reset => vec!["reset", "r"],
             Command::SelectModel => vec!["model", "m"],
             Command::SelectTheme => vec!["theme", "t"],
+            Command::Profile => vec!["profile", "p"],
+            Command::Set => vec!["set"],
+            Command::Context => vec!["context", "ctx"],
+            Command::Usage => vec!["usage"],
             Command::Save => vec!["save", "s"],
             Command::Load => vec!["load", "l"],
+            Command::Export => vec!["export"],
+            Command::Search => vec!["search"],
             Command::History => vec!["history"],
+            Command::Retry => vec!["retry"],
+            Command::Undo => vec!["undo", "u"],
+            Command::Edit => vec!["edit", "e"],
+            Command::Branches => vec!["branches"],
+            Command::Switch => vec!["switch"],
             Command::Help => vec!["help", "h"],
             Command::Invalid => vec![],
         }
@@ -48,14 +72,24 @@ pub fn get_completion_strings() -> Vec<String> {
             }
         }
     }
+    // arguments are only suggested once a command has been typed
+    for parameter in Sampling::PARAMETERS {
+        result.push(format!("/set {}", parameter));
+    }
+    for action in ["add", "remove", "list", "clear"] {
+        result.push(format!("/context {}", action));
+    }
+    for format in ["md", "html", "txt"] {
+        result.push(format!("/export {}", format));
+    }
     result
 }
 
 pub fn parse_command(input: &str) -> Option<Command> {
     let input = input.trim();
-    if input.starts_with('/') {
-        let parts: Vec<&str> = input[1..].split_whitespace().collect();
-        if let Some(command) = parts.get(0) {
+    if let Some(rest) = input.strip_prefix('/') {
+        let parts: Vec<&str> = rest.split_whitespace().collect();
+        if let Some(command) = parts.first() {
             enum_iterator::all::<Command>()
                 .find(|cmd| cmd.strings().contains(&command.to_lowercase().as_str()))
                 .or(Some(Command::Invalid))
@@ -66,3 +100,11 @@ pub fn parse_command(input: &str) -> Option<Command> {
         None
     }
 }
+
+pub fn parse_arguments(input: &str) -> Vec<String> {
+    input
+        .split_whitespace()
+        .skip(1)
+        .map(|s| s.to_string())
+        .collect()
+}
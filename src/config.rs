@@ -1,22 +1,40 @@
-use anyhow::{Context, Result};
+use anyhow::{Context, Result, bail};
 use dirs::config_dir;
 use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
 use std::fs;
 use std::path::PathBuf;
-use toml;
 use yansi::Paint;
 
+use crate::backend::Sampling;
+use crate::tokens::ContextPolicy;
+use crate::usage::Price;
+
 // Configuration options:
+// - Provider (chat backend)
+// - API base URL, organization and project
 // - API key path
+// - API key command
 // - API key env var
 // - System prompt
 // - System prompt path
 // - Model
+// - Reasoning effort
+// - Sampling parameters (temperature, top_p, penalties, seed, stop)
+// - Per-model settings (provider, reasoning, context window)
+// - Context policy (when the context window is exceeded)
+// - Usage tracking (price table, footer, ledger)
+// - Model aliases
+// - Model list cache TTL
+// - Named profiles (provider, base URL, key, model, system prompt)
 // - Max tokens
+// - Retries (count, initial backoff delay)
+// - HTTP client (timeouts, proxy, extra headers)
 // - Single message or REPL for args message
 // - Auto save/load conversations
 // - Disable snail print
 // - Conversations folder
+// - SQLite conversation store (with full-text search)
 // - Configure reedline prompt
 //   - edit mode (emacs, vi)
 //   - command tab completion
@@ -32,14 +50,26 @@ use yansi::Paint;
 //   - wrap width (int, 0 to disable)
 
 const DEFAULT_CONVERSATIONS_FOLDER: &str = "./";
+const DEFAULT_SQLITE_STORE: bool = false;
 const DEFAULT_ENTER_REPL: bool = false;
 const DEFAULT_GREETINGS: bool = true;
-// const DEFAULT_AUTO_SAVE: bool = false;
-// const DEFAULT_AUTO_LOAD: bool = false;
+const DEFAULT_AUTO_SAVE: bool = false;
+const DEFAULT_AUTO_LOAD: bool = false;
 const DEFAULT_ANIMATIONS: bool = true;
 const DEFAULT_SYSTEM_PROMPT: &str = "You are a helpful assistant.";
+const DEFAULT_PROVIDER: &str = "openai";
+const DEFAULT_REQUIRE_API_KEY: bool = true;
 const DEFAULT_MODEL: &str = "gpt-4o";
 const DEFAULT_MAX_TOKENS: u32 = 2048;
+const DEFAULT_MODEL_CACHE_TTL: u64 = 24 * 60 * 60;
+const DEFAULT_CONTEXT_POLICY: ContextPolicy = ContextPolicy::Truncate;
+const DEFAULT_USAGE_FOOTER: bool = true;
+const DEFAULT_USAGE_LEDGER: bool = true;
+const DEFAULT_MAX_RETRIES: u32 = 3;
+const DEFAULT_RETRY_DELAY: f64 = 1.0;
+const DEFAULT_CONNECT_TIMEOUT: u64 = 10;
+const DEFAULT_READ_TIMEOUT: u64 = 300;
+const DEFAULT_TIMEOUT: u64 = 0;
 const DEFAULT_BRACKETED_PASTE: bool = true;
 const DEFAULT_ANSI_COLORS: bool = true;
 const DEFAULT_EDIT_MODE: &str = "emacs";
@@ -49,15 +79,48 @@ const DEFAULT_WRAP_WIDTH: u32 = 100;
 const DEFAULT_REEDLINE_HISTORY: bool = true;
 const DEFAULT_HISTORY_MAX_SIZE: usize = 1000;
 
+#[derive(Clone)]
 pub struct Config {
+    pub default_profile: Option<String>,
+    pub profile: Option<String>,
+    pub profiles: HashMap<String, Profile>,
+    unprofiled: Option<Box<Config>>,
+    /// Settings from the command line, applied again over any profile.
+    overrides: Overrides,
+    pub provider: String,
+    pub api_base: Option<String>,
+    pub organization: Option<String>,
+    pub project: Option<String>,
+    pub require_api_key: bool,
+    pub api_key_env: Option<String>,
+    pub api_key_path: Option<String>,
+    pub api_key_cmd: Option<String>,
     pub system_prompt: String,
     pub model: String,
+    pub title_model: Option<String>,
+    pub models: HashMap<String, ModelConfig>,
+    pub aliases: HashMap<String, String>,
+    pub model_cache_ttl: u64,
+    pub context_policy: ContextPolicy,
+    pub prices: HashMap<String, Price>,
+    pub usage_footer: bool,
+    pub usage_ledger: bool,
     pub max_tokens: u32,
+    pub reasoning_effort: Option<String>,
+    pub sampling: Sampling,
+    pub max_retries: u32,
+    pub retry_delay: f64,
+    pub connect_timeout: u64,
+    pub read_timeout: u64,
+    pub timeout: u64,
+    pub proxy: Option<String>,
+    pub headers: HashMap<String, String>,
     pub conversations_folder: String,
+    pub sqlite_store: bool,
     pub enter_repl: bool,
     pub greetings: bool,
-    // pub auto_save: bool,
-    // pub auto_load: bool,
+    pub auto_save: bool,
+    pub auto_load: bool,
     pub animations: bool, // snail print
     pub bracketed_paste: bool,
     pub ansi_colors: bool,
@@ -72,14 +135,45 @@ pub struct Config {
 impl Default for Config {
     fn default() -> Self {
         Self {
+            default_profile: None,
+            profile: None,
+            profiles: HashMap::new(),
+            unprofiled: None,
+            overrides: Overrides::default(),
+            provider: String::from(DEFAULT_PROVIDER),
+            api_base: None,
+            organization: None,
+            project: None,
+            require_api_key: DEFAULT_REQUIRE_API_KEY,
+            api_key_env: None,
+            api_key_path: None,
+            api_key_cmd: None,
             system_prompt: String::from(DEFAULT_SYSTEM_PROMPT),
             model: String::from(DEFAULT_MODEL),
+            title_model: None,
+            models: HashMap::new(),
+            aliases: HashMap::new(),
+            model_cache_ttl: DEFAULT_MODEL_CACHE_TTL,
+            context_policy: DEFAULT_CONTEXT_POLICY,
+            prices: HashMap::new(),
+            usage_footer: DEFAULT_USAGE_FOOTER,
+            usage_ledger: DEFAULT_USAGE_LEDGER,
             max_tokens: DEFAULT_MAX_TOKENS,
+            reasoning_effort: None,
+            sampling: Sampling::default(),
+            max_retries: DEFAULT_MAX_RETRIES,
+            retry_delay: DEFAULT_RETRY_DELAY,
+            connect_timeout: DEFAULT_CONNECT_TIMEOUT,
+            read_timeout: DEFAULT_READ_TIMEOUT,
+            timeout: DEFAULT_TIMEOUT,
+            proxy: None,
+            headers: HashMap::new(),
             conversations_folder: String::from(DEFAULT_CONVERSATIONS_FOLDER),
+            sqlite_store: DEFAULT_SQLITE_STORE,
             enter_repl: DEFAULT_ENTER_REPL,
             greetings: DEFAULT_GREETINGS,
-            // auto_save: DEFAULT_AUTO_SAVE,
-            // auto_load: DEFAULT_AUTO_LOAD,
+            auto_save: DEFAULT_AUTO_SAVE,
+            auto_load: DEFAULT_AUTO_LOAD,
             animations: DEFAULT_ANIMATIONS,
             bracketed_paste: DEFAULT_BRACKETED_PASTE,
             ansi_colors: DEFAULT_ANSI_COLORS,
@@ -98,22 +192,73 @@ impl Config {
         let system_prompt = prompt.unwrap_or_else(|| match config_toml.system_prompt {
             Some(prompt) => prompt,
             None => {
-                println!(
+                eprintln!(
                     "{}",
                     "No system prompt file provided, using default.".yellow()
                 );
                 String::from(DEFAULT_SYSTEM_PROMPT)
             }
         });
+        let default_profile = config_toml.profile;
+        let profiles = config_toml.profiles.unwrap_or_default();
+        let provider = config_toml
+            .provider
+            .unwrap_or(String::from(DEFAULT_PROVIDER));
+        let api_base = config_toml.api_base;
+        let organization = config_toml.organization;
+        let project = config_toml.project;
+        let require_api_key = config_toml
+            .require_api_key
+            .unwrap_or(DEFAULT_REQUIRE_API_KEY);
+        let api_key_env = config_toml.api_key_env;
+        let api_key_path = config_toml.api_key_path;
+        let api_key_cmd = config_toml.api_key_cmd;
         let model = config_toml.model.unwrap_or(String::from(DEFAULT_MODEL));
+        let title_model = config_toml.title_model;
+        let models = config_toml
+            .models
+            .unwrap_or_default()
+            .into_iter()
+            .map(|(name, model_toml)| (name, ModelConfig::from_toml(model_toml)))
+            .collect();
+        let aliases = config_toml.aliases.unwrap_or_default();
+        let model_cache_ttl = config_toml
+            .model_cache_ttl
+            .unwrap_or(DEFAULT_MODEL_CACHE_TTL);
+        let context_policy = match config_toml.context_policy {
+            Some(name) => ContextPolicy::from_name(&name).unwrap_or_else(|| {
+                eprintln!(
+                    "{} Unknown context policy '{}', defaulting to 'truncate'",
+                    "Warning:".yellow(),
+                    name
+                );
+                DEFAULT_CONTEXT_POLICY
+            }),
+            None => DEFAULT_CONTEXT_POLICY,
+        };
+        let prices = config_toml.prices.unwrap_or_default();
+        let usage_footer = config_toml.usage_footer.unwrap_or(DEFAULT_USAGE_FOOTER);
+        let usage_ledger = config_toml.usage_ledger.unwrap_or(DEFAULT_USAGE_LEDGER);
         let max_tokens = config_toml.max_tokens.unwrap_or(DEFAULT_MAX_TOKENS);
+        let reasoning_effort = config_toml.reasoning_effort;
+        let sampling = config_toml.sampling;
+        let max_retries = config_toml.max_retries.unwrap_or(DEFAULT_MAX_RETRIES);
+        let retry_delay = config_toml.retry_delay.unwrap_or(DEFAULT_RETRY_DELAY);
+        let connect_timeout = config_toml
+            .connect_timeout
+            .unwrap_or(DEFAULT_CONNECT_TIMEOUT);
+        let read_timeout = config_toml.read_timeout.unwrap_or(DEFAULT_READ_TIMEOUT);
+        let timeout = config_toml.timeout.unwrap_or(DEFAULT_TIMEOUT);
+        let proxy = config_toml.proxy;
+        let headers = config_toml.headers.unwrap_or_default();
         let conversations_folder = config_toml
             .conversations_folder
             .unwrap_or(String::from(DEFAULT_CONVERSATIONS_FOLDER));
+        let sqlite_store = config_toml.sqlite_store.unwrap_or(DEFAULT_SQLITE_STORE);
         let enter_repl = config_toml.enter_repl.unwrap_or(DEFAULT_ENTER_REPL);
         let greetings = config_toml.greetings.unwrap_or(DEFAULT_GREETINGS);
-        // let auto_save = config_toml.auto_save.unwrap_or(DEFAULT_AUTO_SAVE);
-        // let auto_load = config_toml.auto_load.unwrap_or(DEFAULT_AUTO_LOAD);
+        let auto_save = config_toml.auto_save.unwrap_or(DEFAULT_AUTO_SAVE);
+        let auto_load = config_toml.auto_load.unwrap_or(DEFAULT_AUTO_LOAD);
         let animations = config_toml.animations.unwrap_or(DEFAULT_ANIMATIONS);
         let bracketed_paste = config_toml
             .bracketed_paste
@@ -136,14 +281,45 @@ impl Config {
 
         Self {
             // api_key,
+            default_profile,
+            profile: None,
+            profiles,
+            unprofiled: None,
+            overrides: Overrides::default(),
+            provider,
+            api_base,
+            organization,
+            project,
+            require_api_key,
+            api_key_env,
+            api_key_path,
+            api_key_cmd,
             system_prompt,
             model,
+            title_model,
+            models,
+            aliases,
+            model_cache_ttl,
+            context_policy,
+            prices,
+            usage_footer,
+            usage_ledger,
             max_tokens,
+            reasoning_effort,
+            sampling,
+            max_retries,
+            retry_delay,
+            connect_timeout,
+            read_timeout,
+            timeout,
+            proxy,
+            headers,
             conversations_folder,
+            sqlite_store,
             enter_repl,
             greetings,
-            // auto_save,
-            // auto_load,
+            auto_save,
+            auto_load,
             animations,
             bracketed_paste,
             ansi_colors,
@@ -155,15 +331,137 @@ impl Config {
             history_max_size,
         }
     }
+
+    /// Returns a copy of this config with the named profile's settings applied.
+    /// Settings from a previously applied profile are discarded first.
+    pub fn with_profile(&self, name: &str) -> Result<Self> {
+        let Some(profile) = self.profiles.get(name) else {
+            bail!("Unknown profile '{}'", name);
+        };
+        let base = match &self.unprofiled {
+            Some(base) => base.as_ref().clone(),
+            None => self.clone(),
+        };
+        let mut config = base.clone();
+        config.unprofiled = Some(Box::new(base));
+        config.profile = Some(name.to_string());
+        if let Some(provider) = &profile.provider {
+            config.provider = provider.clone();
+        }
+        if let Some(api_base) = &profile.api_base {
+            config.api_base = Some(api_base.clone());
+        }
+        if let Some(organization) = &profile.organization {
+            config.organization = Some(organization.clone());
+        }
+        if let Some(project) = &profile.project {
+            config.project = Some(project.clone());
+        }
+        if let Some(require_api_key) = profile.require_api_key {
+            config.require_api_key = require_api_key;
+        }
+        // a profile's key source replaces the top-level one entirely
+        if profile.api_key_env.is_some()
+            || profile.api_key_path.is_some()
+            || profile.api_key_cmd.is_some()
+        {
+            config.api_key_env = profile.api_key_env.clone();
+            config.api_key_path = profile.api_key_path.clone();
+            config.api_key_cmd = profile.api_key_cmd.clone();
+        }
+        if let Some(model) = &profile.model {
+            config.model = model.clone();
+        }
+        if let Some(max_tokens) = profile.max_tokens {
+            config.max_tokens = max_tokens;
+        }
+        if let Some(reasoning_effort) = &profile.reasoning_effort {
+            config.reasoning_effort = Some(reasoning_effort.clone());
+        }
+        config.sampling = config.sampling.merged(&profile.sampling);
+        if let Some(proxy) = &profile.proxy {
+            config.proxy = Some(proxy.clone());
+        }
+        if let Some(headers) = &profile.headers {
+            config.headers.extend(headers.clone());
+        }
+        if let Some(system_prompt) = &profile.system_prompt {
+            config.system_prompt = system_prompt.clone();
+        }
+        config.overrides = self.overrides.clone();
+        config.apply_overrides();
+        Ok(config)
+    }
+
+    /// Returns this config with the settings given on the command line
+    /// applied. They are kept, and take precedence over profiles switched to
+    /// later.
+    pub fn with_overrides(mut self, overrides: Overrides) -> Self {
+        self.overrides = overrides;
+        self.apply_overrides();
+        self
+    }
+
+    fn apply_overrides(&mut self) {
+        let overrides = &self.overrides;
+        // an explicit prompt file takes precedence over the profile's system prompt
+        if let Some(system_prompt) = &overrides.system_prompt {
+            self.system_prompt = system_prompt.clone();
+        }
+        if let Some(api_key_path) = &overrides.api_key_path {
+            self.api_key_path = Some(api_key_path.clone());
+            self.api_key_cmd = None;
+        }
+        if let Some(api_base) = &overrides.api_base {
+            self.api_base = Some(api_base.clone());
+        }
+        self.sampling = self.sampling.merged(&overrides.sampling);
+    }
+
+    /// Names of the configured profiles, sorted alphabetically.
+    pub fn profile_names(&self) -> Vec<String> {
+        let mut names: Vec<String> = self.profiles.keys().cloned().collect();
+        names.sort();
+        names
+    }
 }
 
 #[derive(Serialize, Deserialize, Default, Debug)]
 pub struct ConfigToml {
     // api_key: Option<String>,
+    profile: Option<String>,
+    profiles: Option<HashMap<String, Profile>>,
+    provider: Option<String>,
+    api_base: Option<String>,
+    organization: Option<String>,
+    project: Option<String>,
+    require_api_key: Option<bool>,
+    api_key_env: Option<String>,
+    api_key_path: Option<String>,
+    api_key_cmd: Option<String>,
     system_prompt: Option<String>,
     model: Option<String>,
+    title_model: Option<String>,
+    models: Option<HashMap<String, ModelToml>>,
+    aliases: Option<HashMap<String, String>>,
+    model_cache_ttl: Option<u64>,
+    context_policy: Option<String>,
+    prices: Option<HashMap<String, Price>>,
+    usage_footer: Option<bool>,
+    usage_ledger: Option<bool>,
     max_tokens: Option<u32>,
+    reasoning_effort: Option<String>,
+    #[serde(flatten)]
+    sampling: Sampling,
+    max_retries: Option<u32>,
+    retry_delay: Option<f64>,
+    connect_timeout: Option<u64>,
+    read_timeout: Option<u64>,
+    timeout: Option<u64>,
+    proxy: Option<String>,
+    headers: Option<HashMap<String, String>>,
     conversations_folder: Option<String>,
+    sqlite_store: Option<bool>,
     enter_repl: Option<bool>,
     greetings: Option<bool>,
     auto_save: Option<bool>,
@@ -179,6 +477,80 @@ pub struct ConfigToml {
     history_max_size: Option<usize>,
 }
 
+/// A named bundle of settings from a `[profiles.<name>]` table.
+/// Any option left unset falls back to the top-level value.
+#[derive(Serialize, Deserialize, Default, Debug, Clone)]
+pub struct Profile {
+    provider: Option<String>,
+    api_base: Option<String>,
+    organization: Option<String>,
+    project: Option<String>,
+    require_api_key: Option<bool>,
+    api_key_env: Option<String>,
+    api_key_path: Option<String>,
+    api_key_cmd: Option<String>,
+    model: Option<String>,
+    max_tokens: Option<u32>,
+    reasoning_effort: Option<String>,
+    #[serde(flatten)]
+    sampling: Sampling,
+    proxy: Option<String>,
+    headers: Option<HashMap<String, String>>,
+    system_prompt: Option<String>,
+}
+
+/// Settings given on the command line (`--prompt-path`, `--api-key-path`,
+/// `--base-url` and the sampling flags).
+#[derive(Default, Debug, Clone)]
+pub struct Overrides {
+    pub system_prompt: Option<String>,
+    pub api_key_path: Option<String>,
+    pub api_base: Option<String>,
+    pub sampling: Sampling,
+}
+
+/// Settings that apply to a single model, from a `[models."<name>"]` table.
+#[derive(Default, Debug, Clone)]
+pub struct ModelConfig {
+    pub provider: Option<String>,
+    /// Overrides whether the model is treated as a reasoning model.
+    pub reasoning: Option<bool>,
+    /// Overrides the model's context window size in tokens.
+    pub context_window: Option<u32>,
+    /// Where the API key for the model's provider comes from, when it isn't
+    /// the global setting (or the provider's default variable).
+    pub api_key_env: Option<String>,
+    pub api_key_path: Option<String>,
+    pub api_key_cmd: Option<String>,
+}
+
+impl ModelConfig {
+    fn from_toml(model_toml: ModelToml) -> Self {
+        Self {
+            provider: model_toml.provider,
+            reasoning: model_toml.reasoning,
+            context_window: model_toml.context_window,
+            api_key_env: model_toml.api_key_env,
+            api_key_path: model_toml.api_key_path,
+            api_key_cmd: model_toml.api_key_cmd,
+        }
+    }
+
+    pub fn has_api_key_source(&self) -> bool {
+        self.api_key_env.is_some() || self.api_key_path.is_some() || self.api_key_cmd.is_some()
+    }
+}
+
+#[derive(Serialize, Deserialize, Default, Debug)]
+pub struct ModelToml {
+    provider: Option<String>,
+    reasoning: Option<bool>,
+    context_window: Option<u32>,
+    api_key_env: Option<String>,
+    api_key_path: Option<String>,
+    api_key_cmd: Option<String>,
+}
+
 impl ConfigToml {
     pub fn load(path: PathBuf) -> Result<Self> {
         let config = fs::read_to_string(path).context("Failed to read config file")?;
@@ -202,12 +574,19 @@ fn get_config_path() -> Result<PathBuf> {
     Ok(config_toml_path)
 }
 
-pub fn get_config_toml() -> Option<ConfigToml> {
-    let config_path = get_config_path().ok()?;
+/// Loads the config file, if there is one. An invalid file is an error
+/// rather than falling back to the defaults, which would discard it silently.
+pub fn get_config_toml() -> Result<Option<ConfigToml>> {
+    let Ok(config_path) = get_config_path() else {
+        return Ok(None);
+    };
     if config_path.exists() {
-        ConfigToml::load(config_path).ok()
+        let path = config_path.display().to_string();
+        ConfigToml::load(config_path)
+            .with_context(|| format!("Failed to load {}", path))
+            .map(Some)
     } else {
-        None
+        Ok(None)
     }
 }
 
@@ -222,3 +601,51 @@ pub fn get_history_file_path() -> Result<PathBuf> {
 
     Ok(history_path)
 }
+
+pub fn get_model_cache_path() -> Result<PathBuf> {
+    let cache_path = config_dir()
+        .map(|path| path.join("hey").join("models.json"))
+        .context("Failed to determine config path")?;
+
+    if let Some(parent) = cache_path.parent() {
+        fs::create_dir_all(parent).context("Failed to create config directory")?;
+    }
+
+    Ok(cache_path)
+}
+
+pub fn get_usage_ledger_path() -> Result<PathBuf> {
+    let ledger_path = config_dir()
+        .map(|path| path.join("hey").join("usage.jsonl"))
+        .context("Failed to determine config path")?;
+
+    if let Some(parent) = ledger_path.parent() {
+        fs::create_dir_all(parent).context("Failed to create config directory")?;
+    }
+
+    Ok(ledger_path)
+}
+
+pub fn get_conversation_store_path() -> Result<PathBuf> {
+    let store_path = config_dir()
+        .map(|path| path.join("hey").join("conversations.db"))
+        .context("Failed to determine config path")?;
+
+    if let Some(parent) = store_path.parent() {
+        fs::create_dir_all(parent).context("Failed to create config directory")?;
+    }
+
+    Ok(store_path)
+}
+
+pub fn get_session_path() -> Result<PathBuf> {
+    let session_path = config_dir()
+        .map(|path| path.join("hey").join("session"))
+        .context("Failed to determine config path")?;
+
+    if let Some(parent) = session_path.parent() {
+        fs::create_dir_all(parent).context("Failed to create config directory")?;
+    }
+
+    Ok(session_path)
+}
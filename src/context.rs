@@ -0,0 +1,213 @@
+/* -------------------------------------------------------------------------- */
+/*                                 context.rs                                 */
+/* -------------------------------------------------------------------------- */
+
+// Local files attached to messages, either referenced inline as `@path`
+// (globs allowed) or given with `--context` on the command line, and files
+// pinned for the whole session with `/context`.
+
+use std::path::{Path, PathBuf};
+
+use anyhow::{Context, Result, bail};
+
+/// Files larger than this are skipped rather than sent to the model.
+const MAX_FILE_SIZE: u64 = 1024 * 1024;
+
+/// A file read for attaching to a message.
+pub struct ContextFile {
+    pub path: String,
+    pub content: String,
+}
+
+impl ContextFile {
+    pub fn read(path: &Path) -> Result<Self> {
+        let display = path.to_string_lossy().to_string();
+        let metadata =
+            std::fs::metadata(path).with_context(|| format!("Failed to read '{}'", display))?;
+        if metadata.len() > MAX_FILE_SIZE {
+            bail!(
+                "'{}' is too large to attach ({} KB, the limit is {} KB)",
+                display,
+                metadata.len() / 1024,
+                MAX_FILE_SIZE / 1024
+            );
+        }
+        let bytes = std::fs::read(path).with_context(|| format!("Failed to read '{}'", display))?;
+        let content = String::from_utf8(bytes)
+            .map_err(|_| anyhow::anyhow!("'{}' is not a text file", display))?;
+        Ok(ContextFile {
+            path: display,
+            content,
+        })
+    }
+
+    /// Formats the file as a labelled, fenced block. The fence is made longer
+    /// than any backtick run in the file so that the block can't end early.
+    pub fn to_block(&self) -> String {
+        let longest_run = self
+            .content
+            .split(|c| c != '`')
+            .map(str::len)
+            .max()
+            .unwrap_or(0);
+        let fence = "`".repeat(longest_run.max(2) + 1);
+        let language = Path::new(&self.path)
+            .extension()
+            .map(|ext| ext.to_string_lossy().to_string())
+            .unwrap_or_default();
+        let content = self.content.trim_end_matches('\n');
+        format!(
+            "File: {}\n{}{}\n{}\n{}",
+            self.path, fence, language, content, fence
+        )
+    }
+}
+
+/// Returns the `@path` references in a message, in order of appearance.
+pub fn find_references(message: &str) -> Vec<String> {
+    message
+        .split_whitespace()
+        .filter_map(|word| word.strip_prefix('@'))
+        .map(trim_reference)
+        .filter(|reference| !reference.is_empty())
+        .map(str::to_string)
+        .collect()
+}
+
+/// Strips punctuation that usually follows a reference in a sentence.
+pub fn trim_reference(reference: &str) -> &str {
+    reference.trim_end_matches([',', ';', ':', '!', '?', ')', '"', '\''])
+}
+
+/// Expands a reference (`~`, globs) to the files it names. Directories are
+/// not attached, and a reference that matches nothing returns no paths.
+pub fn resolve_reference(reference: &str) -> Vec<PathBuf> {
+    let expanded = shellexpand::tilde(reference).to_string();
+    if !expanded.contains(['*', '?', '[']) {
+        let path = PathBuf::from(&expanded);
+        return if path.is_file() {
+            vec![path]
+        } else {
+            Vec::new()
+        };
+    }
+    match glob::glob(&expanded) {
+        Ok(paths) => paths
+            .filter_map(|path| path.ok())
+            .filter(|path| path.is_file())
+            .collect(),
+        Err(_) => Vec::new(),
+    }
+}
+
+/// Appends the given files to a message as fenced blocks.
+pub fn attach_files(message: &str, files: &[ContextFile]) -> String {
+    if files.is_empty() {
+        return message.to_string();
+    }
+    let blocks = files
+        .iter()
+        .map(ContextFile::to_block)
+        .collect::<Vec<_>>()
+        .join("\n\n");
+    if message.is_empty() {
+        blocks
+    } else {
+        format!("{}\n\n{}", message, blocks)
+    }
+}
+
+/// Resolves and reads every `@path` reference in a message together with the
+/// `extra` paths. Returns the files, and a warning for each reference that
+/// matched nothing or couldn't be read.
+pub fn collect_files(message: &str, extra: &[String]) -> (Vec<ContextFile>, Vec<String>) {
+    let mut files: Vec<ContextFile> = Vec::new();
+    let mut warnings = Vec::new();
+    let references = extra.iter().cloned().chain(find_references(message));
+    for reference in references {
+        let paths = resolve_reference(&reference);
+        if paths.is_empty() {
+            warnings.push(format!("No files match '{}'", reference));
+        }
+        for path in paths {
+            match ContextFile::read(&path) {
+                Ok(file) if files.iter().any(|f| f.path == file.path) => {}
+                Ok(file) => files.push(file),
+                Err(e) => warnings.push(format!("{:#}", e)),
+            }
+        }
+    }
+    (files, warnings)
+}
+
+/* -------------------------------------------------------------------------- */
+/*                                Pinned Files                                */
+/* -------------------------------------------------------------------------- */
+
+/// Files pinned with `/context add`. Pinned references are resolved and read
+/// again before every request, so edits (and new glob matches) are picked up.
+#[derive(Default, Clone)]
+pub struct ContextSet {
+    references: Vec<String>,
+}
+
+impl ContextSet {
+    pub fn from_references(references: Vec<String>) -> Self {
+        ContextSet { references }
+    }
+
+    pub fn references(&self) -> &[String] {
+        &self.references
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.references.is_empty()
+    }
+
+    /// Pins a reference, returning the files it currently matches.
+    pub fn add(&mut self, reference: &str) -> Result<Vec<ContextFile>> {
+        let reference = trim_reference(reference.strip_prefix('@').unwrap_or(reference));
+        if self.references.iter().any(|r| r == reference) {
+            bail!("'{}' is already pinned", reference);
+        }
+        let (files, warnings) = collect_files("", &[reference.to_string()]);
+        if files.is_empty() {
+            bail!(
+                "{}",
+                warnings
+                    .first()
+                    .cloned()
+                    .unwrap_or_else(|| format!("No files match '{}'", reference))
+            );
+        }
+        self.references.push(reference.to_string());
+        Ok(files)
+    }
+
+    /// Unpins a reference, given as pinned or as its position in the list.
+    pub fn remove(&mut self, reference: &str) -> Result<String> {
+        let reference = reference.strip_prefix('@').unwrap_or(reference);
+        let index = match reference.parse::<usize>() {
+            Ok(n) if (1..=self.references.len()).contains(&n) => n - 1,
+            _ => self
+                .references
+                .iter()
+                .position(|r| r == reference)
+                .with_context(|| format!("'{}' is not pinned", reference))?,
+        };
+        Ok(self.references.remove(index))
+    }
+
+    /// Reads the pinned files, with a warning for each that can't be read.
+    pub fn read(&self) -> (Vec<ContextFile>, Vec<String>) {
+        collect_files("", &self.references)
+    }
+
+    /// Formats the pinned files as a message for the model.
+    pub fn to_message(files: &[ContextFile]) -> String {
+        attach_files(
+            "The user has pinned these files as context for the conversation:",
+            files,
+        )
+    }
+}
@@ -1,108 +1,702 @@
-use anyhow::Result;
+use anyhow::{Context, Result, bail};
 use async_openai::types::{
     ChatCompletionRequestAssistantMessageContent, ChatCompletionRequestMessage,
     ChatCompletionRequestUserMessageContent,
 };
 
+use chrono::{DateTime, Local};
+use serde::{Deserialize, Serialize};
 use yansi::Paint;
 
 use crate::{
+    backend::Sampling,
     render::{Highlighter, wrap_line},
-    utils::{new_assistant_message, new_system_message, new_user_message},
+    usage::TurnUsage,
+    utils::{message_text, new_assistant_message, new_system_message, new_user_message},
 };
 
+/// Version of the saved conversation format, written to every file. Files
+/// without a version, saved as a bare array of messages, are migrated when
+/// loaded.
+/// - 1: a tree of messages with metadata, with the branches created by `/edit`
+const SCHEMA_VERSION: u32 = 1;
+
+/// A conversation is a tree of messages rooted at the system prompt. Editing
+/// an earlier message starts a new branch next to the old one, and one branch
+/// at a time is active: it's the one shown, continued and sent to the model.
 pub struct Conversation {
+    /// Identifies the conversation across saves, independent of its filename.
+    pub id: String,
+    pub title: Option<String>,
+    pub created: DateTime<Local>,
+    pub updated: DateTime<Local>,
+    /// Model and profile the conversation was last saved with.
+    pub model: Option<String>,
+    pub profile: Option<String>,
+    /// The messages of the active branch, from the system prompt on.
     pub messages: Vec<ChatCompletionRequestMessage>,
+    /// Every message of every branch. The system prompt comes first, and
+    /// every other message after the one it follows.
+    nodes: Vec<Node>,
+    /// The indices in `nodes` of the active branch's messages.
+    path: Vec<usize>,
+    /// Sampling parameters the conversation was saved with, if any.
+    pub sampling: Option<Sampling>,
+    /// References of the files pinned with `/context` when saved.
+    pub context: Vec<String>,
+    /// Stands in for the oldest messages in requests once the conversation
+    /// has outgrown the context window. The messages themselves are kept.
+    pub summary: Option<Summary>,
+}
+
+/// A message in the conversation tree, with what's known about it.
+#[derive(Clone)]
+struct Node {
+    message: ChatCompletionRequestMessage,
+    /// The message this one follows; only the system prompt has none.
+    parent: Option<usize>,
+    /// When the message was added. Messages from files saved before
+    /// timestamps were stored have none.
+    timestamp: Option<DateTime<Local>>,
+    /// Token usage of an assistant message.
+    usage: Option<TurnUsage>,
+    /// Whether an assistant message was interrupted before completing.
+    interrupted: bool,
+    /// The text of a user message as typed, when files were attached to it.
+    typed: Option<String>,
+}
+
+impl Node {
+    fn new(message: ChatCompletionRequestMessage, parent: Option<usize>) -> Self {
+        Node {
+            message,
+            parent,
+            timestamp: Some(Local::now()),
+            usage: None,
+            interrupted: false,
+            typed: None,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+pub struct Summary {
+    pub text: String,
+    /// Messages before this index (after the system prompt) are summarized.
+    pub covers: usize,
+}
+
+/// A branch of the conversation, from the system prompt to a message that
+/// hasn't been replied to.
+pub struct Branch {
+    /// Number of messages after the system prompt.
+    pub length: usize,
+    /// Number of messages (after the system prompt) shared with the active
+    /// branch.
+    pub shared: usize,
+    pub active: bool,
+    /// The last user message and the reply to it, if any.
+    pub question: String,
+    pub answer: Option<String>,
+}
+
+/// The on-disk format of a saved conversation.
+#[derive(Serialize, Deserialize)]
+struct ConversationFile {
+    version: u32,
+    id: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    title: Option<String>,
+    created: DateTime<Local>,
+    updated: DateTime<Local>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    model: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    profile: Option<String>,
+    #[serde(default)]
+    sampling: Sampling,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    context: Vec<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    summary: Option<Summary>,
+    /// Every message of every branch.
+    messages: Vec<SavedMessage>,
+    /// Index of the last message of the active branch, when it isn't the
+    /// last message.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    active: Option<usize>,
+}
+
+/// A message with what's known about it, stored alongside its role and
+/// content.
+#[derive(Serialize, Deserialize)]
+struct SavedMessage {
+    #[serde(flatten)]
+    message: ChatCompletionRequestMessage,
+    /// Index of the message this one follows, when it isn't the previous
+    /// one, so that a conversation without branches is a plain list.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    parent: Option<usize>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    timestamp: Option<DateTime<Local>>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    usage: Option<TurnUsage>,
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    interrupted: bool,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    typed: Option<String>,
+}
+
+/// A user message, remembering the text typed if files were attached to it.
+fn user_node(typed: String, content: String, parent: Option<usize>) -> Node {
+    let typed = (typed != content).then_some(typed);
+    Node {
+        typed,
+        ..Node::new(new_user_message(content), parent)
+    }
+}
+
+/// A new conversation id: the creation time followed by random hex digits.
+fn new_id(created: &DateTime<Local>) -> String {
+    format!(
+        "{}-{:06x}",
+        created.format("%Y%m%d%H%M%S"),
+        fastrand::u32(..0x1000000)
+    )
 }
 
 impl Conversation {
     pub fn new(system_prompt: String) -> Self {
         let system_message = new_system_message(system_prompt);
-        Conversation {
-            messages: vec![system_message],
-        }
+        Conversation::from_messages(vec![system_message])
     }
 
-    pub fn add_user_message(&mut self, content: String) {
-        let message = new_user_message(content);
-        self.messages.push(message);
+    /// Adds a user message made of the text typed and the files attached to
+    /// it. The text is kept so that `/edit` starts from it.
+    pub fn add_user_message_with_files(&mut self, typed: String, content: String) {
+        let node = user_node(typed, content, self.path.last().copied());
+        self.push(node);
     }
 
     pub fn add_assistant_message(&mut self, content: String) {
         let message = new_assistant_message(content);
-        self.messages.push(message);
+        self.push(Node::new(message, self.path.last().copied()));
+    }
+
+    /// Adds the partial text of an interrupted response.
+    pub fn add_truncated_assistant_message(&mut self, content: String) {
+        let message = new_assistant_message(content);
+        let mut node = Node::new(message, self.path.last().copied());
+        node.interrupted = true;
+        self.push(node);
+    }
+
+    /// Adds a message to the end of the active branch.
+    fn push(&mut self, node: Node) {
+        self.messages.push(node.message.clone());
+        self.path.push(self.nodes.len());
+        self.nodes.push(node);
+    }
+
+    pub fn set_system_prompt(&mut self, system_prompt: String) {
+        let system_message = new_system_message(system_prompt);
+        match self.messages.first_mut() {
+            Some(ChatCompletionRequestMessage::System(_)) => {
+                self.messages[0] = system_message.clone();
+                self.nodes[self.path[0]].message = system_message;
+            }
+            _ => {
+                // the old first message now follows the system prompt
+                for node in &mut self.nodes {
+                    node.parent = Some(node.parent.map_or(0, |parent| parent + 1));
+                }
+                self.nodes
+                    .insert(0, Node::new(system_message.clone(), None));
+                self.path = std::iter::once(0)
+                    .chain(self.path.iter().map(|i| i + 1))
+                    .collect();
+                self.messages.insert(0, system_message);
+            }
+        }
     }
 
+    /// Starts over with the same system prompt, as a new conversation.
     pub fn reset(&mut self) {
-        self.messages = self.messages[..1].to_vec();
+        let now = Local::now();
+        self.id = new_id(&now);
+        self.title = None;
+        self.created = now;
+        self.updated = now;
+        // the system prompt is the root, the first message
+        self.nodes.truncate(1);
+        self.path.truncate(1);
+        self.messages.truncate(1);
+        self.summary = None;
+    }
+
+    /// Records the usage of the last (assistant) message.
+    pub fn record_usage(&mut self, usage: TurnUsage) {
+        if let Some(&last) = self.path.last() {
+            self.nodes[last].usage = Some(usage);
+        }
+    }
+
+    /// The usage of every response, including those on inactive branches.
+    pub fn usage(&self) -> Vec<TurnUsage> {
+        self.nodes
+            .iter()
+            .filter_map(|node| node.usage.clone())
+            .collect()
+    }
+
+    /// Replaces the user message at `index` of the active branch with a new
+    /// version, on a new branch that ends there. The old version and the
+    /// messages after it are kept on their branch.
+    pub fn edit(&mut self, index: usize, typed: String, content: String) -> Result<()> {
+        let parent = *index
+            .checked_sub(1)
+            .and_then(|previous| self.path.get(previous))
+            .context("The system prompt can't be edited.")?;
+        self.nodes.push(user_node(typed, content, Some(parent)));
+        let mut path = self.path[..index].to_vec();
+        path.push(self.nodes.len() - 1);
+        self.set_path(path);
+        Ok(())
+    }
+
+    /// The text of the message at `index` of the active branch as it was
+    /// typed, without the files attached to it.
+    pub fn typed_text(&self, index: usize) -> &str {
+        match self.path.get(index).map(|&i| &self.nodes[i]) {
+            Some(Node {
+                typed: Some(typed), ..
+            }) => typed,
+            Some(node) => message_text(&node.message),
+            None => "",
+        }
+    }
+
+    /// The branches of the conversation, in the order they were created.
+    pub fn branches(&self) -> Vec<Branch> {
+        self.branch_ends()
+            .into_iter()
+            .map(|leaf| {
+                let path = self.path_to(leaf);
+                let shared = path
+                    .iter()
+                    .zip(&self.path)
+                    .take_while(|(a, b)| a == b)
+                    .count();
+                let last_user = path.iter().rposition(|&i| {
+                    matches!(self.nodes[i].message, ChatCompletionRequestMessage::User(_))
+                });
+                let question = last_user
+                    .map(|i| message_text(&self.nodes[path[i]].message).to_string())
+                    .unwrap_or_default();
+                let answer = last_user
+                    .and_then(|i| path.get(i + 1))
+                    .map(|&i| message_text(&self.nodes[i].message).to_string());
+                Branch {
+                    length: path.len() - 1,
+                    shared: shared.saturating_sub(1),
+                    active: path == self.path,
+                    question,
+                    answer,
+                }
+            })
+            .collect()
+    }
+
+    /// Makes the `n`th branch (counting from 1) the active one.
+    pub fn switch(&mut self, n: usize) -> Result<()> {
+        let leaf = *self
+            .branch_ends()
+            .get(n.wrapping_sub(1))
+            .with_context(|| format!("There is no branch {}", n))?;
+        self.set_path(self.path_to(leaf));
+        Ok(())
+    }
+
+    /// Moves the active branch back to the last user message, so that a new
+    /// answer to it is added next to the old one. Returns the last message of
+    /// the branch before, to `restore` if no answer comes.
+    pub fn retry(&mut self) -> Result<usize> {
+        let last_user = self
+            .last_user_message()
+            .context("There is no message to retry.")?;
+        let leaf = self.path[self.path.len() - 1];
+        self.set_path(self.path[..=last_user].to_vec());
+        Ok(leaf)
+    }
+
+    /// Makes the branch ending at a message the active one again.
+    pub fn restore(&mut self, leaf: usize) {
+        self.set_path(self.path_to(leaf));
+    }
+
+    /// Removes the last user message of the active branch and the answer to
+    /// it, returning its text and whether other versions of the exchange
+    /// (from `/retry` or `/edit`) were kept on their branches.
+    pub fn undo(&mut self) -> Result<(String, bool)> {
+        let last_user = self
+            .last_user_message()
+            .context("There is nothing to undo.")?;
+        let removed = self.path.split_off(last_user);
+        let text = message_text(&self.nodes[removed[0]].message).to_string();
+        self.set_path(self.path.clone());
+        // deepest first: removing a message only moves the ones after it
+        for &index in removed.iter().rev() {
+            if !self.children(index).is_empty() {
+                return Ok((text, true));
+            }
+            self.remove(index);
+        }
+        Ok((text, false))
+    }
+
+    /// The position of the last user message in the active branch.
+    fn last_user_message(&self) -> Option<usize> {
+        self.messages
+            .iter()
+            .rposition(|message| matches!(message, ChatCompletionRequestMessage::User(_)))
+    }
+
+    /// Removes a message that no other message follows. The active branch
+    /// must not contain it.
+    fn remove(&mut self, index: usize) {
+        self.nodes.remove(index);
+        for node in &mut self.nodes {
+            if let Some(parent) = node.parent.as_mut()
+                && *parent > index
+            {
+                *parent -= 1;
+            }
+        }
+        for i in &mut self.path {
+            if *i > index {
+                *i -= 1;
+            }
+        }
+    }
+
+    /// The last message of every branch: the messages nothing follows, and
+    /// the end of the active branch, which `/undo` can leave before others.
+    fn branch_ends(&self) -> Vec<usize> {
+        (0..self.nodes.len())
+            .filter(|&i| self.children(i).is_empty() || self.path.last() == Some(&i))
+            .collect()
+    }
+
+    fn children(&self, index: usize) -> Vec<usize> {
+        (0..self.nodes.len())
+            .filter(|&i| self.nodes[i].parent == Some(index))
+            .collect()
+    }
+
+    /// The path from the system prompt to a message.
+    fn path_to(&self, index: usize) -> Vec<usize> {
+        let mut path = vec![index];
+        while let Some(parent) = self.nodes[*path.last().unwrap()].parent {
+            path.push(parent);
+        }
+        path.reverse();
+        path
+    }
+
+    fn set_path(&mut self, path: Vec<usize>) {
+        let shared = path
+            .iter()
+            .zip(&self.path)
+            .take_while(|(a, b)| a == b)
+            .count();
+        // a summary of messages that are no longer on the active branch
+        // doesn't apply to it
+        if self.summary.as_ref().is_some_and(|s| s.covers > shared) {
+            self.summary = None;
+        }
+        self.messages = path
+            .iter()
+            .map(|&i| self.nodes[i].message.clone())
+            .collect();
+        self.path = path;
+    }
+
+    /// The messages sent in requests after `prefix` (the system prompt and
+    /// pinned files): the summary of earlier turns, if any, and the turns it
+    /// doesn't cover.
+    pub fn request_messages(
+        &self,
+        prefix: &[ChatCompletionRequestMessage],
+    ) -> Vec<ChatCompletionRequestMessage> {
+        let mut messages = prefix.to_vec();
+        let start = match &self.summary {
+            Some(summary) => {
+                messages.push(new_system_message(format!(
+                    "Summary of the earlier conversation:\n\n{}",
+                    summary.text
+                )));
+                summary.covers
+            }
+            None => 1,
+        };
+        messages.extend_from_slice(&self.messages[start.min(self.messages.len())..]);
+        messages
     }
 
     pub fn print_messages(&self, highlighter: &mut Option<Highlighter>, wrap_width: u32) {
-        self.messages.iter().for_each(|msg| match msg {
-            ChatCompletionRequestMessage::User(msg) => match msg.content {
-                ChatCompletionRequestUserMessageContent::Text(ref content) => {
-                    println!("\n{}{}", "> ".magenta(), content.green());
+        self.path.iter().enumerate().for_each(|(i, &index)| {
+            let node = &self.nodes[index];
+            match &node.message {
+                ChatCompletionRequestMessage::User(msg) => {
+                    if let ChatCompletionRequestUserMessageContent::Text(ref content) = msg.content
+                    {
+                        println!("\n{}{}", "> ".magenta(), content.green());
+                        self.print_version(index);
+                    }
                 }
-                _ => {}
-            },
-            ChatCompletionRequestMessage::Assistant(msg) => match msg.content {
-                Some(ChatCompletionRequestAssistantMessageContent::Text(ref content)) => {
-                    println!();
-                    for line in content.split_inclusive("\n") {
-                        let processed_line = if let Some(h) = highlighter {
-                            let highlighted = h.highlight_line(line);
-                            wrap_line(&highlighted, wrap_width)
-                        } else {
-                            wrap_line(line, wrap_width)
-                        };
-                        print!("{}", processed_line);
+                ChatCompletionRequestMessage::Assistant(msg) => {
+                    if let Some(ChatCompletionRequestAssistantMessageContent::Text(ref content)) =
+                        msg.content
+                    {
+                        println!();
+                        for line in content.split_inclusive("\n") {
+                            let processed_line = if let Some(h) = highlighter {
+                                let highlighted = h.highlight_line(line);
+                                wrap_line(&highlighted, wrap_width)
+                            } else {
+                                wrap_line(line, wrap_width)
+                            };
+                            print!("{}", processed_line);
+                        }
+                        if node.interrupted {
+                            print!("\n\n{}", "[Response interrupted]".dim());
+                        } else if let Some(usage) = &node.usage {
+                            print!("\n\n{}", usage.footer().dim());
+                        }
+                        println!();
+                        self.print_version(index);
+                        if self.summary.as_ref().is_some_and(|s| s.covers == i + 1) {
+                            println!(
+                                "\n{}",
+                                "[Messages above are summarized for the model]".dim()
+                            );
+                        }
                     }
-                    println!();
                 }
                 _ => {}
-            },
-            _ => {}
+            }
         })
     }
 
+    /// Notes which version of a message is shown, if there are others.
+    fn print_version(&self, index: usize) {
+        let Some(parent) = self.nodes[index].parent else {
+            return;
+        };
+        let versions = self.children(parent);
+        if versions.len() > 1 {
+            let version = versions.iter().position(|&i| i == index).unwrap_or(0) + 1;
+            println!(
+                "{}",
+                format!("[Version {} of {}]", version, versions.len()).dim()
+            );
+        }
+    }
+
     pub fn transcript(&self) -> String {
-        self.messages[1..]
-            .iter()
-            .map(|msg| match msg {
-                ChatCompletionRequestMessage::User(user_msg) => {
-                    let content = match &user_msg.content {
-                        ChatCompletionRequestUserMessageContent::Text(content) => content,
-                        _ => "",
-                    };
-                    format!("User: {}\n", content)
-                }
-                ChatCompletionRequestMessage::Assistant(assistant_msg) => {
-                    let content = match &assistant_msg.content {
-                        Some(ChatCompletionRequestAssistantMessageContent::Text(content)) => {
-                            content
-                        }
-                        _ => "",
-                    };
-                    format!("Assistant: {}\n", content)
-                }
-                _ => "".to_string(),
-            })
-            .collect::<String>()
+        format_transcript(self.messages.get(1..).unwrap_or_default())
     }
 
     pub fn save_to_json_file(&self, path: &str) -> Result<()> {
-        let json = serde_json::to_string_pretty(&self.messages)?;
-        std::fs::write(path, json)?;
+        std::fs::write(path, self.to_json()?)?;
         Ok(())
     }
 
+    /// Serializes the conversation in the current saved format.
+    pub fn to_json(&self) -> Result<String> {
+        let messages = self
+            .nodes
+            .iter()
+            .enumerate()
+            .map(|(i, node)| SavedMessage {
+                message: node.message.clone(),
+                parent: node
+                    .parent
+                    .filter(|&parent| Some(parent) != i.checked_sub(1)),
+                timestamp: node.timestamp,
+                usage: node.usage.clone(),
+                interrupted: node.interrupted,
+                typed: node.typed.clone(),
+            })
+            .collect();
+        let active = self.path.last().copied();
+        let file = ConversationFile {
+            version: SCHEMA_VERSION,
+            id: self.id.clone(),
+            title: self.title.clone(),
+            created: self.created,
+            updated: self.updated,
+            model: self.model.clone(),
+            profile: self.profile.clone(),
+            sampling: self.sampling.clone().unwrap_or_default(),
+            context: self.context.clone(),
+            summary: self.summary.clone(),
+            messages,
+            active: active.filter(|&active| active + 1 != self.nodes.len()),
+        };
+        Ok(serde_json::to_string_pretty(&file)?)
+    }
+
     pub fn from_json_file(path: &str) -> Result<Self> {
         let data = std::fs::read_to_string(path)?;
-        let messages: Vec<ChatCompletionRequestMessage> = serde_json::from_str(&data)?;
-        Ok(Conversation::from_messages(messages))
+        // the file's modification time is the best guess at when a file saved
+        // before conversations were dated was saved
+        let modified = std::fs::metadata(path)
+            .and_then(|metadata| metadata.modified())
+            .map(DateTime::<Local>::from)
+            .unwrap_or_else(|_| Local::now());
+        Conversation::from_json(&data, modified)
+            .with_context(|| format!("Invalid conversation file {}", path))
+    }
+
+    /// Parses a saved conversation, migrating a bare array of messages (dated
+    /// `legacy_saved`) to the current format.
+    pub fn from_json(data: &str, legacy_saved: DateTime<Local>) -> Result<Self> {
+        let value: serde_json::Value = serde_json::from_str(data)?;
+        if value.is_array() {
+            let messages: Vec<ChatCompletionRequestMessage> = serde_json::from_value(value)?;
+            if !matches!(
+                messages.first(),
+                Some(ChatCompletionRequestMessage::System(_))
+            ) {
+                bail!("The conversation doesn't start with a system prompt");
+            }
+            return Ok(Conversation::from_messages(messages).saved_at(legacy_saved));
+        }
+        let Some(version) = value.get("version") else {
+            bail!("Not a saved conversation");
+        };
+        match version.as_u64() {
+            Some(version) if version <= SCHEMA_VERSION as u64 => {}
+            _ => bail!(
+                "Saved in a newer format (version {}); update hey to load it",
+                version
+            ),
+        }
+        let file: ConversationFile = serde_json::from_value(value)?;
+        if !matches!(
+            file.messages.first().map(|saved| &saved.message),
+            Some(ChatCompletionRequestMessage::System(_))
+        ) {
+            bail!("The conversation doesn't start with a system prompt");
+        }
+        let mut nodes = Vec::with_capacity(file.messages.len());
+        for (i, saved) in file.messages.into_iter().enumerate() {
+            let parent = saved.parent.or(i.checked_sub(1));
+            if parent.is_some_and(|parent| parent >= i) {
+                bail!("Message {} follows a later message", i);
+            }
+            nodes.push(Node {
+                message: saved.message,
+                parent,
+                timestamp: saved.timestamp,
+                usage: saved.usage,
+                interrupted: saved.interrupted,
+                typed: saved.typed,
+            });
+        }
+        let active = file.active.unwrap_or(nodes.len() - 1);
+        if active >= nodes.len() {
+            bail!("The active branch ends at a missing message");
+        }
+        let mut conversation = Conversation {
+            id: file.id,
+            title: file.title,
+            created: file.created,
+            updated: file.updated,
+            model: file.model,
+            profile: file.profile,
+            messages: Vec::new(),
+            nodes,
+            path: Vec::new(),
+            sampling: Some(file.sampling),
+            context: file.context,
+            summary: None,
+        };
+        conversation.set_path(conversation.path_to(active));
+        // a summary must cover messages of the active branch after the
+        // system prompt, or it's dropped
+        conversation.summary = file
+            .summary
+            .filter(|summary| (1..=conversation.messages.len()).contains(&summary.covers));
+        Ok(conversation)
+    }
+
+    /// Dates a migrated conversation, whose messages have no timestamps.
+    fn saved_at(mut self, saved: DateTime<Local>) -> Self {
+        self.id = new_id(&saved);
+        self.created = saved;
+        self.updated = saved;
+        for node in &mut self.nodes {
+            node.timestamp = None;
+        }
+        self
+    }
+
+    /// Dates the messages of a conversation without branches, such as an
+    /// imported one, in order.
+    pub fn with_timestamps(mut self, timestamps: Vec<Option<DateTime<Local>>>) -> Self {
+        for (node, timestamp) in self.nodes.iter_mut().zip(timestamps) {
+            node.timestamp = timestamp;
+        }
+        self
     }
 
+    /// A conversation without branches.
     pub fn from_messages(messages: Vec<ChatCompletionRequestMessage>) -> Self {
-        Conversation { messages }
+        let now = Local::now();
+        let nodes = messages
+            .iter()
+            .enumerate()
+            .map(|(i, message)| Node::new(message.clone(), i.checked_sub(1)))
+            .collect();
+        Conversation {
+            id: new_id(&now),
+            title: None,
+            created: now,
+            updated: now,
+            model: None,
+            profile: None,
+            path: (0..messages.len()).collect(),
+            messages,
+            nodes,
+            sampling: None,
+            context: Vec::new(),
+            summary: None,
+        }
     }
 }
+
+/// Formats messages as "User: ..." / "Assistant: ..." lines.
+pub fn format_transcript(messages: &[ChatCompletionRequestMessage]) -> String {
+    messages
+        .iter()
+        .map(|msg| match msg {
+            ChatCompletionRequestMessage::User(user_msg) => {
+                let content = match &user_msg.content {
+                    ChatCompletionRequestUserMessageContent::Text(content) => content,
+                    _ => "",
+                };
+                format!("User: {}\n", content)
+            }
+            ChatCompletionRequestMessage::Assistant(assistant_msg) => {
+                let content = match &assistant_msg.content {
+                    Some(ChatCompletionRequestAssistantMessageContent::Text(content)) => content,
+                    _ => "",
+                };
+                format!("Assistant: {}\n", content)
+            }
+            _ => "".to_string(),
+        })
+        .collect::<String>()
+}
@@ -3,7 +3,10 @@
 /* -------------------------------------------------------------------------- */
 
 use std::borrow::Cow;
+use std::cell::RefCell;
+use std::collections::HashMap;
 use std::path::PathBuf;
+use std::sync::{Arc, RwLock};
 
 use crossterm::cursor::SetCursorStyle;
 use nu_ansi_term::{Color as NuColor, Style};
@@ -16,13 +19,14 @@ use reedline::{
 };
 
 use crate::{
-    commands::{Command, get_completion_strings, parse_command},
+    commands::{Command, get_completion_strings, parse_arguments, parse_command},
     config::Config,
+    context::{resolve_reference, trim_reference},
 };
 
 pub enum Input {
     Message(String),
-    Command(Command),
+    Command(Command, Vec<String>),
     Invalid,
 }
 
@@ -74,24 +78,55 @@ impl EditorConfig {
 #[derive(Clone)]
 struct CommandCompleter {
     commands: Vec<String>,
+    models: Arc<RwLock<Vec<String>>>,
 }
 
 impl CommandCompleter {
-    pub fn new(commands: Vec<String>) -> Self {
-        CommandCompleter { commands }
+    pub fn new(commands: Vec<String>, models: Arc<RwLock<Vec<String>>>) -> Self {
+        CommandCompleter { commands, models }
     }
 }
 
 impl Completer for CommandCompleter {
     fn complete(&mut self, line: &str, pos: usize) -> Vec<Suggestion> {
         let line = &line[..pos.min(line.len())];
+        let word = line.rsplit(char::is_whitespace).next().unwrap_or(line);
+        if let Some(partial) = word.strip_prefix('@') {
+            return complete_path(partial, pos);
+        }
         if !line.starts_with('/') {
             return Vec::new();
         }
+        if let Some((command, model)) = line.split_once(' ')
+            && matches!(
+                parse_command(command),
+                Some(Command::SelectModel | Command::Retry)
+            )
+        {
+            let start = pos - model.len();
+            let models = self.models.read().unwrap();
+            return models
+                .iter()
+                .filter(|m| m.starts_with(model))
+                .map(|m| Suggestion {
+                    value: m.clone(),
+                    span: Span::new(start, pos),
+                    append_whitespace: false,
+                    ..Default::default()
+                })
+                .collect();
+        }
+        if let Some((command, rest)) = line.split_once(' ')
+            && parse_command(command) == Some(Command::Context)
+            && let Some(("add" | "remove", _)) = rest.split_once(' ')
+        {
+            return complete_path(word, pos);
+        }
         let start = pos - line.len();
+        let has_arguments = line.contains(' ');
         self.commands
             .iter()
-            .filter(|cmd| cmd.starts_with(line))
+            .filter(|cmd| cmd.contains(' ') == has_arguments && cmd.starts_with(line))
             .map(|cmd| Suggestion {
                 value: cmd.clone(),
                 span: Span::new(start, pos),
@@ -101,14 +136,50 @@ impl Completer for CommandCompleter {
     }
 }
 
+/// Completes the file or directory name being typed (after `@` in messages).
+fn complete_path(partial: &str, pos: usize) -> Vec<Suggestion> {
+    let (dir, prefix) = match partial.rfind('/') {
+        Some(i) => (&partial[..=i], &partial[i + 1..]),
+        None => ("", partial),
+    };
+    let expanded = shellexpand::tilde(if dir.is_empty() { "." } else { dir });
+    let Ok(entries) = std::fs::read_dir(expanded.as_ref()) else {
+        return Vec::new();
+    };
+    let mut suggestions: Vec<Suggestion> = entries
+        .filter_map(|entry| entry.ok())
+        .filter_map(|entry| {
+            let name = entry.file_name().to_string_lossy().to_string();
+            // hidden files are only offered once a dot is typed
+            if !name.starts_with(prefix) || (name.starts_with('.') && !prefix.starts_with('.')) {
+                return None;
+            }
+            let is_dir = entry.file_type().ok()?.is_dir();
+            Some(Suggestion {
+                value: format!("{}{}{}", dir, name, if is_dir { "/" } else { "" }),
+                span: Span::new(pos - partial.len(), pos),
+                append_whitespace: !is_dir,
+                ..Default::default()
+            })
+        })
+        .collect();
+    suggestions.sort_by(|a, b| a.value.cmp(&b.value));
+    suggestions
+}
+
 pub struct Editor {
     line_editor: Reedline,
     prompt: EditorPrompt,
+    models: Arc<RwLock<Vec<String>>>,
 }
 
 impl Editor {
     pub fn new(editor_config: EditorConfig) -> Self {
-        let cmd_completer = Box::new(CommandCompleter::new(get_completion_strings()));
+        let models = Arc::new(RwLock::new(Vec::new()));
+        let cmd_completer = Box::new(CommandCompleter::new(
+            get_completion_strings(),
+            models.clone(),
+        ));
         let cmd_completion_menu = Box::new(
             ColumnarMenu::default()
                 .with_name("completion_menu")
@@ -190,14 +261,21 @@ impl Editor {
         Editor {
             line_editor,
             prompt,
+            models,
         }
     }
 
+    /// Sets the model names offered when completing `/model <name>` and
+    /// `/retry <name>`.
+    pub fn set_models(&self, models: Vec<String>) {
+        *self.models.write().unwrap() = models;
+    }
+
     pub fn get_input(&mut self) -> Input {
         let input_str = match self.line_editor.read_line(&self.prompt) {
             Ok(Signal::Success(buffer)) => buffer.trim().to_string(),
             Ok(Signal::CtrlD) | Ok(Signal::CtrlC) => {
-                return Input::Command(Command::Exit);
+                return Input::Command(Command::Exit, Vec::new());
             }
             x => {
                 eprintln!("Error reading input: {:?}", x);
@@ -209,7 +287,7 @@ impl Editor {
             s if s.is_empty() => Input::Invalid,
             s => {
                 if let Some(cmd) = parse_command(&s) {
-                    Input::Command(cmd)
+                    Input::Command(cmd, parse_arguments(&s))
                 } else {
                     Input::Message(s)
                 }
@@ -259,7 +337,11 @@ impl Prompt for EditorPrompt {
     }
 }
 
-struct PromptHighlighter {}
+struct PromptHighlighter {
+    /// Whether each `@path` reference in the line matches any files, kept
+    /// between keystrokes so that references aren't resolved on every one.
+    references: RefCell<HashMap<String, bool>>,
+}
 
 enum ParseState {
     Start,
@@ -271,11 +353,31 @@ enum ParseState {
 
 impl PromptHighlighter {
     pub fn new() -> Self {
-        PromptHighlighter {}
+        PromptHighlighter {
+            references: RefCell::new(HashMap::new()),
+        }
     }
 
     pub fn parse_line(&self, line: &str) -> Vec<(InputPart, String)> {
         let splits = line.split_inclusive(|c: char| c.is_whitespace());
+        // only the references still in the line are kept
+        let mut references = self.references.borrow_mut();
+        let mut known = std::mem::take(&mut *references);
+        // distinguishes `@path` file references, resolved or not, from text
+        let mut message_part = |word: &str| {
+            let Some(reference) = word.trim_end().strip_prefix('@').map(trim_reference) else {
+                return InputPart::MessageText;
+            };
+            if reference.is_empty() {
+                return InputPart::MessageText;
+            }
+            let matched = known
+                .remove(reference)
+                .or_else(|| references.get(reference).copied())
+                .unwrap_or_else(|| !resolve_reference(reference).is_empty());
+            references.insert(reference.to_string(), matched);
+            InputPart::FileReference(matched)
+        };
 
         let mut parts = Vec::new();
         let mut parse_state: ParseState = ParseState::Start;
@@ -300,12 +402,12 @@ impl PromptHighlighter {
                         parts.push((InputPart::Whitespace, split.to_string()));
                         ParseState::Start
                     } else {
-                        parts.push((InputPart::MessageText, split.to_string()));
+                        parts.push((message_part(split), split.to_string()));
                         ParseState::Message
                     }
                 }
                 ParseState::Message => {
-                    parts.push((InputPart::MessageText, split.to_string()));
+                    parts.push((message_part(split), split.to_string()));
                     ParseState::Message
                 }
                 ParseState::CommandParsed => {
@@ -348,6 +450,8 @@ enum InputPart {
     Whitespace,
     Slash,
     MessageText,
+    /// A `@path` reference, and whether it matches any files.
+    FileReference(bool),
     ValidCommand(Command),
     InvalidCommand,
     ValidArgument,
@@ -360,6 +464,8 @@ impl InputPart {
             InputPart::Whitespace => Style::default(),
             InputPart::Slash => Style::default(),
             InputPart::MessageText => Style::new().fg(NuColor::Green),
+            InputPart::FileReference(true) => Style::new().fg(NuColor::Magenta).underline(),
+            InputPart::FileReference(false) => Style::new().fg(NuColor::Yellow),
             InputPart::ValidCommand(_) => Style::new().fg(NuColor::Cyan),
             InputPart::InvalidCommand => Style::new().fg(NuColor::Yellow),
             InputPart::ValidArgument => Style::new().fg(NuColor::Blue),
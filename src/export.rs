@@ -0,0 +1,209 @@
+/* -------------------------------------------------------------------------- */
+/*                                 export.rs                                  */
+/* -------------------------------------------------------------------------- */
+
+// Exporting conversations as documents (`/export` and `hey export`): Markdown
+// with a heading per turn, self-contained HTML with code blocks highlighted
+// using the terminal's syntax definitions, or plain text.
+
+use std::path::Path;
+
+use anyhow::Result;
+use async_openai::types::ChatCompletionRequestMessage;
+use bat::assets::HighlightingAssets;
+use clap::ValueEnum;
+use pulldown_cmark::{CodeBlockKind, Event, Options, Parser, Tag, TagEnd, html::push_html};
+use syntect::{highlighting::Theme, html::highlighted_html_for_string, parsing::SyntaxSet};
+
+use crate::conversation::Conversation;
+use crate::render::{is_ansi_theme, load_syntax_set};
+use crate::utils::message_text;
+
+/// Used for code blocks when the configured theme uses terminal colors,
+/// which have no meaning outside the terminal.
+const FALLBACK_THEME: &str = "GitHub";
+
+const STYLE: &str = "
+body { max-width: 48rem; margin: 2rem auto; padding: 0 1rem; font-family: system-ui, sans-serif; line-height: 1.5; color: #1f2328; }
+h1 { margin-bottom: 0.25rem; }
+.meta { color: #656d76; margin-top: 0; }
+section { border-top: 1px solid #d0d7de; padding-top: 0.5rem; }
+section h2 { font-size: 1rem; text-transform: uppercase; letter-spacing: 0.05em; color: #656d76; }
+section.user h2 { color: #8250df; }
+pre { padding: 0.75rem 1rem; border-radius: 6px; overflow-x: auto; }
+code { font-family: ui-monospace, monospace; font-size: 0.9em; }
+table { border-collapse: collapse; }
+th, td { border: 1px solid #d0d7de; padding: 0.25rem 0.5rem; }
+";
+
+#[derive(Debug, Clone, Copy, PartialEq, ValueEnum)]
+pub enum Format {
+    #[value(name = "md", alias = "markdown")]
+    Markdown,
+    Html,
+    #[value(name = "txt", alias = "text")]
+    Text,
+}
+
+impl Format {
+    pub fn extension(&self) -> &str {
+        match self {
+            Format::Markdown => "md",
+            Format::Html => "html",
+            Format::Text => "txt",
+        }
+    }
+
+    /// The format with a name (or alias) such as `md` or `html`, if any.
+    pub fn from_name(name: &str) -> Option<Self> {
+        Format::from_str(name, true).ok()
+    }
+
+    /// The format matching a file's extension, if any.
+    pub fn from_path(path: &str) -> Option<Self> {
+        Format::from_name(Path::new(path).extension()?.to_str()?)
+    }
+}
+
+/// Renders the active branch of a conversation as a document. `theme` is the
+/// highlighting theme for HTML code blocks.
+pub fn export(conversation: &Conversation, format: Format, theme: &str) -> Result<String> {
+    let turns = turns(conversation);
+    let title = conversation
+        .title
+        .as_deref()
+        .unwrap_or("Untitled Conversation");
+    let meta = meta(conversation);
+    match format {
+        Format::Markdown => {
+            let mut document = format!("# {}\n\n_{}_\n", title, meta);
+            for (role, text) in turns {
+                document.push_str(&format!("\n## {}\n\n{}\n", role, text.trim_end()));
+            }
+            Ok(document)
+        }
+        Format::Html => {
+            let syntax_set = load_syntax_set()?;
+            let assets = HighlightingAssets::from_binary();
+            let theme = assets.get_theme(if is_ansi_theme(theme) {
+                FALLBACK_THEME
+            } else {
+                theme
+            });
+            let mut document = format!(
+                "<!DOCTYPE html>\n<html lang=\"en\">\n<head>\n<meta charset=\"utf-8\">\n\
+                 <meta name=\"viewport\" content=\"width=device-width, initial-scale=1\">\n\
+                 <title>{title}</title>\n<style>{}</style>\n</head>\n<body>\n\
+                 <h1>{title}</h1>\n<p class=\"meta\">{}</p>\n",
+                STYLE,
+                escape(&meta),
+                title = escape(title),
+            );
+            for (role, text) in turns {
+                document.push_str(&format!(
+                    "<section class=\"{}\">\n<h2>{}</h2>\n{}</section>\n",
+                    role.to_lowercase(),
+                    role,
+                    markdown_to_html(text, &syntax_set, theme)
+                ));
+            }
+            document.push_str("</body>\n</html>\n");
+            Ok(document)
+        }
+        Format::Text => {
+            let mut document = format!(
+                "{}\n{}\n\n{}\n",
+                title,
+                "=".repeat(title.chars().count()),
+                meta
+            );
+            for (role, text) in turns {
+                document.push_str(&format!("\n{}:\n{}\n", role, text.trim_end()));
+            }
+            Ok(document)
+        }
+    }
+}
+
+/// The user and assistant messages of the active branch, with their roles.
+fn turns(conversation: &Conversation) -> Vec<(&'static str, &str)> {
+    conversation
+        .messages
+        .iter()
+        .filter_map(|message| match message {
+            ChatCompletionRequestMessage::User(_) => Some(("User", message_text(message))),
+            ChatCompletionRequestMessage::Assistant(_) => {
+                Some(("Assistant", message_text(message)))
+            }
+            _ => None,
+        })
+        .collect()
+}
+
+fn meta(conversation: &Conversation) -> String {
+    let date = conversation.created.format("%Y-%m-%d %H:%M").to_string();
+    match &conversation.model {
+        Some(model) => format!("{} · {}", model, date),
+        None => date,
+    }
+}
+
+/// Converts Markdown to HTML, highlighting fenced code blocks by language.
+/// Raw HTML in the text is escaped.
+fn markdown_to_html(text: &str, syntax_set: &SyntaxSet, theme: &Theme) -> String {
+    let options =
+        Options::ENABLE_TABLES | Options::ENABLE_STRIKETHROUGH | Options::ENABLE_TASKLISTS;
+    // the language and text of the code block being read
+    let mut code_block: Option<(String, String)> = None;
+    let events = Parser::new_ext(text, options).filter_map(|event| {
+        if let Some((language, code)) = &mut code_block {
+            return match event {
+                Event::Text(text) => {
+                    code.push_str(&text);
+                    None
+                }
+                Event::End(TagEnd::CodeBlock) => {
+                    let html = highlight(code, language, syntax_set, theme);
+                    code_block = None;
+                    Some(Event::Html(html.into()))
+                }
+                _ => None,
+            };
+        }
+        match event {
+            Event::Start(Tag::CodeBlock(kind)) => {
+                let language = match kind {
+                    CodeBlockKind::Fenced(info) => info
+                        .split_whitespace()
+                        .next()
+                        .unwrap_or_default()
+                        .to_string(),
+                    CodeBlockKind::Indented => String::new(),
+                };
+                code_block = Some((language, String::new()));
+                None
+            }
+            // HTML in a message is shown as written, not run by the browser
+            Event::Html(html) | Event::InlineHtml(html) => Some(Event::Text(html)),
+            event => Some(event),
+        }
+    });
+    let mut html = String::new();
+    push_html(&mut html, events);
+    html
+}
+
+fn highlight(code: &str, language: &str, syntax_set: &SyntaxSet, theme: &Theme) -> String {
+    let syntax = syntax_set
+        .find_syntax_by_token(language)
+        .unwrap_or_else(|| syntax_set.find_syntax_plain_text());
+    highlighted_html_for_string(code, syntax_set, syntax, theme)
+        .unwrap_or_else(|_| format!("<pre><code>{}</code></pre>\n", escape(code)))
+}
+
+fn escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
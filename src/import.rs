@@ -0,0 +1,397 @@
+/* -------------------------------------------------------------------------- */
+/*                                 import.rs                                  */
+/* -------------------------------------------------------------------------- */
+
+// Importing conversations from other tools (`hey import`). ChatGPT's data
+// export has a `conversations.json` with every conversation as a tree of
+// messages (its `mapping`); the branch that was last shown is imported.
+
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+
+use anyhow::{Context, Result};
+use chrono::{DateTime, Local};
+use serde::Deserialize;
+use serde_json::Value;
+use yansi::Paint;
+
+use crate::config::Config;
+use crate::conversation::Conversation;
+use crate::render::snailprint;
+use crate::store::Store;
+use crate::utils::{
+    new_assistant_message, new_system_message, new_user_message, title_to_filename,
+};
+
+#[derive(Deserialize)]
+struct ChatGptConversation {
+    #[serde(default)]
+    title: Option<String>,
+    #[serde(default)]
+    create_time: Option<f64>,
+    #[serde(default)]
+    update_time: Option<f64>,
+    mapping: HashMap<String, MappingNode>,
+    /// The last message of the branch that was shown.
+    #[serde(default)]
+    current_node: Option<String>,
+    #[serde(default)]
+    default_model_slug: Option<String>,
+    #[serde(default, alias = "conversation_id")]
+    id: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct MappingNode {
+    #[serde(default)]
+    message: Option<ChatGptMessage>,
+    #[serde(default)]
+    parent: Option<String>,
+    #[serde(default)]
+    children: Vec<String>,
+}
+
+#[derive(Deserialize)]
+struct ChatGptMessage {
+    author: Author,
+    #[serde(default)]
+    create_time: Option<f64>,
+    #[serde(default)]
+    content: Option<Content>,
+    /// Who the message is addressed to; messages to tools aren't shown.
+    #[serde(default)]
+    recipient: Option<String>,
+    #[serde(default)]
+    metadata: Metadata,
+}
+
+#[derive(Deserialize)]
+struct Author {
+    role: String,
+}
+
+#[derive(Deserialize)]
+struct Content {
+    content_type: String,
+    /// Text, and for `multimodal_text` objects such as image references.
+    #[serde(default)]
+    parts: Vec<Value>,
+}
+
+#[derive(Deserialize, Default)]
+struct Metadata {
+    #[serde(default)]
+    attachments: Vec<Attachment>,
+    #[serde(default)]
+    model_slug: Option<String>,
+    #[serde(default)]
+    is_visually_hidden_from_conversation: bool,
+}
+
+#[derive(Deserialize)]
+struct Attachment {
+    #[serde(default)]
+    name: Option<String>,
+}
+
+/// A conversation converted to hey's format, with the attachments it had.
+struct Imported {
+    conversation: Conversation,
+    skipped_attachments: Vec<String>,
+}
+
+/// Imports the conversations of a ChatGPT data export (its
+/// `conversations.json`, or the folder containing it) into the conversation
+/// store or folder, and reports what couldn't be imported.
+pub fn import_chatgpt(config: &Config, path: &str) -> Result<()> {
+    let mut path = PathBuf::from(path);
+    if path.is_dir() {
+        path.push("conversations.json");
+    }
+    let data = std::fs::read_to_string(&path)
+        .with_context(|| format!("Failed to read {}", path.display()))?;
+    let exported: Vec<Value> = serde_json::from_str(&data)
+        .with_context(|| format!("Invalid ChatGPT export {}", path.display()))?;
+
+    let mut imported = Vec::new();
+    let mut invalid = 0;
+    let mut empty = 0;
+    for conversation in exported {
+        match serde_json::from_value::<ChatGptConversation>(conversation) {
+            Ok(conversation) => match convert(config, conversation) {
+                Some(conversation) => imported.push(conversation),
+                None => empty += 1,
+            },
+            Err(_) => invalid += 1,
+        }
+    }
+
+    // a conversation that can't be saved doesn't stop the others, and the
+    // first error is reported
+    let mut failed = 0;
+    let mut first_error = None;
+    let mut record_failure = |e: anyhow::Error| {
+        failed += 1;
+        first_error.get_or_insert(e);
+    };
+    let destination = if config.sqlite_store {
+        let mut store = Store::open(config)?;
+        for import in &imported {
+            if let Err(e) = store.save(&import.conversation) {
+                record_failure(e);
+            }
+        }
+        "the conversation store".to_string()
+    } else {
+        let folder = shellexpand::full(&config.conversations_folder)
+            .map_err(|e| anyhow::anyhow!("Path expansion failed: {}", e))?
+            .to_string();
+        std::fs::create_dir_all(&folder)
+            .with_context(|| "Failed to create conversations directory")?;
+        for import in &imported {
+            let file = conversation_file(Path::new(&folder), &import.conversation);
+            if let Err(e) = import
+                .conversation
+                .save_to_json_file(&file)
+                .with_context(|| format!("Failed to write {}", file))
+            {
+                record_failure(e);
+            }
+        }
+        folder
+    };
+
+    snailprint(
+        &format!(
+            "\n{} {} into {}.\n",
+            "Imported".green(),
+            conversations(imported.len() - failed),
+            destination.blue()
+        ),
+        1000,
+    );
+    if empty > 0 {
+        snailprint(
+            &format!(
+                "{}\n",
+                format!("Skipped {} without messages.", conversations(empty)).dim()
+            ),
+            1000,
+        );
+    }
+    if invalid > 0 {
+        snailprint(
+            &format!(
+                "{} Skipped {} that couldn't be read.\n",
+                "Warning:".yellow(),
+                conversations(invalid)
+            ),
+            1000,
+        );
+    }
+    if let Some(e) = first_error {
+        snailprint(
+            &format!(
+                "{} Failed to save {}: {:#}\n",
+                "Warning:".yellow(),
+                conversations(failed),
+                e
+            ),
+            1000,
+        );
+    }
+    let with_attachments: Vec<&Imported> = imported
+        .iter()
+        .filter(|import| !import.skipped_attachments.is_empty())
+        .collect();
+    if !with_attachments.is_empty() {
+        snailprint(&format!("\n{}\n", "Attachments not imported:".bold()), 1000);
+        for import in with_attachments {
+            snailprint(
+                &format!(
+                    "{} {}\n",
+                    format!(
+                        "{}:",
+                        import
+                            .conversation
+                            .title
+                            .as_deref()
+                            .unwrap_or("Untitled Conversation")
+                    )
+                    .cyan(),
+                    import.skipped_attachments.join(", ")
+                ),
+                1000,
+            );
+        }
+    }
+    println!();
+    Ok(())
+}
+
+/// Converts the shown branch of a conversation, or returns `None` if it has
+/// no messages to import.
+fn convert(config: &Config, conversation: ChatGptConversation) -> Option<Imported> {
+    let mut messages = Vec::new();
+    let mut timestamps = Vec::new();
+    let mut skipped_attachments = Vec::new();
+    let mut model = conversation.default_model_slug.clone();
+    for node in active_branch(&conversation) {
+        let Some(message) = &node.message else {
+            continue;
+        };
+        skipped_attachments.extend(
+            message
+                .metadata
+                .attachments
+                .iter()
+                .map(|attachment| attachment.name.clone().unwrap_or("file".to_string())),
+        );
+        let is_user = match message.author.role.as_str() {
+            "user" => true,
+            "assistant" => false,
+            // system messages, custom instructions and tool output
+            _ => continue,
+        };
+        if message.metadata.is_visually_hidden_from_conversation
+            || message.recipient.as_deref().is_some_and(|r| r != "all")
+        {
+            continue;
+        }
+        let Some(content) = &message.content else {
+            continue;
+        };
+        // reasoning, code run by tools, browsing results and the like
+        if content.content_type != "text" && content.content_type != "multimodal_text" {
+            continue;
+        }
+        let mut text = Vec::new();
+        for part in &content.parts {
+            match part {
+                Value::String(part) => text.push(part.as_str()),
+                // uploaded files are listed in the metadata
+                _ if message.metadata.attachments.is_empty() => {
+                    skipped_attachments.push("image".to_string())
+                }
+                _ => {}
+            }
+        }
+        let text = text.join("\n").trim().to_string();
+        if text.is_empty() {
+            continue;
+        }
+        if !is_user && let Some(slug) = &message.metadata.model_slug {
+            model = Some(slug.clone());
+        }
+        // consecutive messages from the same side (e.g. before and after a
+        // tool was used) become one
+        match messages.last_mut() {
+            Some((last_is_user, last_text)) if *last_is_user == is_user => {
+                *last_text = format!("{}\n\n{}", last_text, text);
+            }
+            _ => {
+                messages.push((is_user, text));
+                timestamps.push(message.create_time.and_then(to_local));
+            }
+        }
+    }
+    if messages.is_empty() {
+        return None;
+    }
+
+    let created = conversation
+        .create_time
+        .and_then(to_local)
+        .or(timestamps.iter().flatten().next().copied())
+        .unwrap_or_else(Local::now);
+    let updated = conversation
+        .update_time
+        .and_then(to_local)
+        .unwrap_or(created);
+    let messages = std::iter::once(new_system_message(config.system_prompt.clone()))
+        .chain(messages.into_iter().map(|(is_user, text)| {
+            if is_user {
+                new_user_message(text)
+            } else {
+                new_assistant_message(text)
+            }
+        }))
+        .collect();
+    let timestamps = std::iter::once(Some(created)).chain(timestamps).collect();
+    let mut imported = Conversation::from_messages(messages).with_timestamps(timestamps);
+    // the same id on every import, so that importing again replaces it
+    if let Some(id) = &conversation.id {
+        let digits: String = id.chars().filter(char::is_ascii_hexdigit).take(6).collect();
+        imported.id = format!("{}-{}", created.format("%Y%m%d%H%M%S"), digits);
+    }
+    imported.title = conversation.title.filter(|title| !title.trim().is_empty());
+    imported.created = created;
+    imported.updated = updated;
+    imported.model = model;
+    Some(Imported {
+        conversation: imported,
+        skipped_attachments,
+    })
+}
+
+/// The nodes from the root to the current node. Without a current node, the
+/// latest reply is followed at every turn.
+fn active_branch(conversation: &ChatGptConversation) -> Vec<&MappingNode> {
+    let mapping = &conversation.mapping;
+    let mut branch = Vec::new();
+    match conversation
+        .current_node
+        .as_ref()
+        .and_then(|id| mapping.get(id))
+    {
+        Some(mut node) => {
+            branch.push(node);
+            // the length limit guards against cycles
+            while let Some(parent) = node.parent.as_ref().and_then(|id| mapping.get(id))
+                && branch.len() <= mapping.len()
+            {
+                branch.push(parent);
+                node = parent;
+            }
+            branch.reverse();
+        }
+        None => {
+            let mut next = mapping.values().find(|node| node.parent.is_none());
+            while let Some(node) = next
+                && branch.len() <= mapping.len()
+            {
+                branch.push(node);
+                next = node.children.last().and_then(|id| mapping.get(id));
+            }
+        }
+    }
+    branch
+}
+
+fn conversations(count: usize) -> String {
+    if count == 1 {
+        "1 conversation".to_string()
+    } else {
+        format!("{} conversations", count)
+    }
+}
+
+fn to_local(timestamp: f64) -> Option<DateTime<Local>> {
+    DateTime::from_timestamp_millis((timestamp * 1000.0) as i64)
+        .map(|date| date.with_timezone(&Local))
+}
+
+/// The file for an imported conversation: named after its title, or also
+/// its id if another conversation has that name.
+fn conversation_file(folder: &Path, conversation: &Conversation) -> String {
+    let filename = title_to_filename(conversation.title.as_deref().unwrap_or_default());
+    let mut path = folder.join(&filename);
+    if path.exists()
+        && !Conversation::from_json_file(&path.to_string_lossy())
+            .is_ok_and(|saved| saved.id == conversation.id)
+    {
+        let stem = filename.trim_end_matches(".json");
+        path = folder.join(format!("{}_{}.json", stem, conversation.id));
+    }
+    path.to_string_lossy().to_string()
+}
@@ -1,11 +1,4 @@
-// TODO: Add load flag to load a conversation in comand line (--load, -l)
-// TODO: Add context referencing command (/context, /c)
-//   TODO: Add a context referenceing flag (--context, -c)
-// TODO: Implement auto saving and loading (default off)
-// TODO: Support models like o3 and gpt-5
 // TODO: organize config into global, reedline config and response config
-// TODO: support api_key path
-//   TODO: support api_key path flag (--api-key-path, -k)
 // TODO: add config options for reedline prompt style (color, indicator char, etc.)
 
 // Experiments
@@ -14,33 +7,149 @@
 // Support command tab completion for commands
 // Allow editor as prompt input as supported by reedline
 
-use anyhow::{Context, Result};
-use async_openai::{Client, config::OpenAIConfig};
-use clap::Parser;
+use std::io::{IsTerminal, Read};
+
+use anyhow::{Context, Result, bail};
+use clap::{Parser, Subcommand};
 use yansi::{self, Paint};
 
+mod backend;
 mod commands;
 mod config;
+mod context;
 mod conversation;
 mod editor;
+mod export;
+mod import;
+mod models;
 mod render;
 mod repl;
 mod response;
+mod retry;
+mod session;
+mod store;
+mod tokens;
+mod usage;
 mod utils;
 
-use config::{Config, get_config_toml};
+use backend::{Backend, Sampling};
+use config::{Config, Overrides, get_config_toml};
+use context::ContextSet;
+use conversation::Conversation;
 
 #[derive(Parser, Debug)]
+// `hey help me ...` is a message, not a request for help
+#[command(disable_help_subcommand = true)]
 struct Args {
     /// Optional path to a system prompt text file
     #[arg(long, short)]
     prompt_path: Option<String>,
 
+    /// Optional path to a file containing the API key
+    #[arg(long, short = 'k')]
+    api_key_path: Option<String>,
+
+    /// Name of a profile from the config file to use
+    #[arg(long)]
+    profile: Option<String>,
+
+    /// Base URL of an OpenAI-compatible API (e.g. http://localhost:11434/v1)
+    #[arg(long)]
+    base_url: Option<String>,
+
+    /// File to attach to the message (can be given multiple times, globs allowed)
+    #[arg(long = "context", short = 'c')]
+    context: Vec<String>,
+
+    /// Sampling temperature (0 to 2)
+    #[arg(long)]
+    temperature: Option<f32>,
+
+    /// Nucleus sampling probability mass (0 to 1)
+    #[arg(long)]
+    top_p: Option<f32>,
+
+    /// Presence penalty (-2 to 2)
+    #[arg(long)]
+    presence_penalty: Option<f32>,
+
+    /// Frequency penalty (-2 to 2)
+    #[arg(long)]
+    frequency_penalty: Option<f32>,
+
+    /// Seed for more deterministic sampling
+    #[arg(long)]
+    seed: Option<i64>,
+
+    /// Stop sequence (can be given up to 4 times)
+    #[arg(long)]
+    stop: Vec<String>,
+
+    /// Conversation to continue: a saved file, or an id in the conversation store
+    #[arg(long, short, conflicts_with = "continue_latest")]
+    load: Option<String>,
+
+    /// Continue the most recently saved conversation
+    #[arg(long = "continue")]
+    continue_latest: bool,
+
+    /// Start a new conversation instead of resuming the last session (with auto_load)
+    #[arg(long, conflicts_with_all = ["load", "continue_latest"])]
+    new: bool,
+
+    #[command(subcommand)]
+    command: Option<CliCommand>,
+
     /// Message to send to the model. If not provided, enters interactive REPL mode.
+    /// Input piped to stdin is appended to the message, or replaces `{stdin}` in it.
+    /// Use `--` before a message that starts with a subcommand name.
     #[arg(trailing_var_arg = true, allow_hyphen_values = true)]
     message: Vec<String>,
 }
 
+#[derive(Subcommand, Debug)]
+enum CliCommand {
+    /// Report token usage and cost recorded in the usage ledger
+    Usage {
+        /// Only include usage on or after this date (YYYY-MM-DD)
+        #[arg(long)]
+        since: Option<String>,
+    },
+    /// Search saved conversations (requires `sqlite_store`)
+    Search {
+        /// Words to search for
+        #[arg(required = true)]
+        terms: Vec<String>,
+    },
+    /// Export a saved conversation as Markdown, HTML or plain text
+    Export {
+        /// The conversation file, or the id of a stored conversation
+        conversation: String,
+        /// Document format (defaults to the output file's extension, or md)
+        #[arg(long, short, value_enum)]
+        format: Option<export::Format>,
+        /// Write the document to this file instead of standard output
+        #[arg(long, short)]
+        output: Option<String>,
+    },
+    /// Import conversations from another tool into the conversations folder
+    /// (or the store, with `sqlite_store`)
+    Import {
+        #[command(subcommand)]
+        source: ImportSource,
+    },
+}
+
+#[derive(Subcommand, Debug)]
+enum ImportSource {
+    /// Import a ChatGPT data export
+    #[command(name = "chatgpt")]
+    ChatGpt {
+        /// The export's conversations.json, or the folder containing it
+        path: String,
+    },
+}
+
 #[tokio::main]
 async fn main() -> Result<()> {
     let args = Args::parse();
@@ -51,11 +160,39 @@ async fn main() -> Result<()> {
     } else {
         None
     };
-    let config = if let Some(config_toml) = get_config_toml() {
-        Config::from_toml(config_toml, prompt)
+    let mut config = if let Some(config_toml) = get_config_toml()? {
+        Config::from_toml(config_toml, prompt.clone())
     } else {
         Config::default()
     };
+    if let Some(profile) = args.profile.or(config.default_profile.clone()) {
+        config = config.with_profile(&profile)?;
+    }
+    let sampling = Sampling {
+        temperature: args.temperature,
+        top_p: args.top_p,
+        presence_penalty: args.presence_penalty,
+        frequency_penalty: args.frequency_penalty,
+        seed: args.seed,
+        stop: (!args.stop.is_empty()).then_some(args.stop),
+    };
+    config = config.with_overrides(Overrides {
+        system_prompt: prompt,
+        api_key_path: args.api_key_path,
+        api_base: args.base_url,
+        sampling: sampling.clone(),
+    });
+    config.sampling.validate()?;
+
+    // piped output is kept free of animations, colors, escapes and wrapping
+    let interactive_output = std::io::stdout().is_terminal();
+    if !interactive_output {
+        config.animations = false;
+        config.ansi_colors = false;
+        config.syntax_highlighting = false;
+        config.wrap_width = 0;
+        render::set_terminal_output(false);
+    }
 
     if config.ansi_colors {
         yansi::enable();
@@ -69,18 +206,38 @@ async fn main() -> Result<()> {
         render::disable_animations();
     }
 
-    let api_key = std::env::var("OPENAI_API_KEY")
-        .context("Please set the OPENAI_API_KEY environment variable to your OpenAI API key.")?;
-
-    // let api_key = match args.api_key_path {
-    //     Some(path) => std::fs::read_to_string(path)
-    //         .context("Failed to read API key from file")?
-    //         .trim()
-    //         .to_string(),
-    //     None => std::env::var("OPENAI_API_KEY").context(
-    //         "Please set the OPENAI_API_KEY environment variable to your OpenAI API key.",
-    //     )?,
-    // };
+    if let Some(command) = args.command {
+        return match command {
+            CliCommand::Usage { since } => usage::print_report(since.as_deref()),
+            CliCommand::Search { terms } => {
+                let results = store::Store::open(&config)?.search(&terms)?;
+                store::print_results(&results);
+                Ok(())
+            }
+            CliCommand::Export {
+                conversation,
+                format,
+                output,
+            } => {
+                let (conversation, _) = session::open(&config, &conversation)?;
+                let format = format
+                    .or_else(|| output.as_deref().and_then(export::Format::from_path))
+                    .unwrap_or(export::Format::Markdown);
+                let document = export::export(&conversation, format, &config.theme)?;
+                match output {
+                    Some(path) => std::fs::write(&path, document)
+                        .with_context(|| format!("Failed to write {}", path))?,
+                    None => print!("{}", document),
+                }
+                Ok(())
+            }
+            CliCommand::Import {
+                source: ImportSource::ChatGpt { path },
+            } => import::import_chatgpt(&config, &path),
+        };
+    }
+
+    let backend = Backend::from_config(&config)?;
 
     // utils::api_check(&api_key).await?;
 
@@ -95,38 +252,141 @@ async fn main() -> Result<()> {
     //     String::from(DEFAULT_SYSTEM_PROMPT)
     // };
 
-    let openai_config = OpenAIConfig::new().with_api_key(api_key);
-    let client = Client::with_config(openai_config);
+    let stdin_input = if std::io::stdin().is_terminal() {
+        None
+    } else {
+        let mut input = String::new();
+        std::io::stdin()
+            .read_to_string(&mut input)
+            .context("Failed to read from stdin")?;
+        Some(input).filter(|input| !input.trim().is_empty())
+    };
+    let message = utils::compose_message(&args.message, stdin_input);
+    let interactive = std::io::stdin().is_terminal() && interactive_output;
 
-    if args.message.is_empty() {
-        // interactive REPL
-        let mut repl = repl::ReadEvalPrintLoop::new(client, config);
+    if message.is_empty() && !std::io::stdin().is_terminal() {
+        bail!("No message provided and stdin is empty");
+    }
+
+    // a conversation to continue, and the file it was saved in
+    let resumed = if let Some(reference) = &args.load {
+        Some(session::open(&config, reference)?)
+    } else if args.continue_latest {
+        Some(session::open_latest(&config)?)
+    } else if config.auto_load && !args.new && message.is_empty() {
+        session::open_last(&config).unwrap_or_else(|e| {
+            eprintln!(
+                "{} Failed to resume the last session: {:#}",
+                "Warning:".yellow(),
+                e
+            );
+            None
+        })
+    } else {
+        None
+    };
+    let resuming = resumed.is_some();
+    let (mut conversation, file) = match resumed {
+        Some((mut conversation, file)) => {
+            // sampling parameters given on the command line override the saved ones
+            if let Some(saved) = &conversation.sampling {
+                conversation.sampling = Some(saved.merged(&sampling));
+            }
+            (conversation, file)
+        }
+        None => (Conversation::new(config.system_prompt.clone()), None),
+    };
+
+    if message.is_empty() {
+        // interactive REPL, with any --context files attached to the first message
+        let mut repl =
+            repl::ReadEvalPrintLoop::with_conversation(backend, config, conversation, file);
+        repl.attach_to_next_message(args.context);
         repl.run().await?;
-    } else if config.enter_repl {
+        return Ok(());
+    }
+
+    // only the words typed are searched for @path references: piped input
+    // can come from anywhere, and mustn't be able to attach local files
+    let (files, warnings) = context::collect_files(&args.message.join(" "), &args.context);
+    for warning in warnings {
+        eprintln!("{} {}", "Warning:".yellow(), warning);
+    }
+    let content = context::attach_files(&message, &files);
+    conversation.add_user_message_with_files(message, content);
+
+    if config.enter_repl && interactive {
         // enter REPL with initial message
-        let messages = vec![
-            utils::new_system_message(config.system_prompt.clone()),
-            utils::new_user_message(args.message.join(" ")),
-        ];
-        let mut repl = repl::ReadEvalPrintLoop::with_conversation(
-            client,
-            config,
-            conversation::Conversation::from_messages(messages),
-        );
+        let mut repl =
+            repl::ReadEvalPrintLoop::with_conversation(backend, config, conversation, file);
         repl.run().await?;
     } else {
-        // single message
-        let messages = vec![
-            utils::new_system_message(config.system_prompt),
-            utils::new_user_message(args.message.join(" ")),
-        ];
-        let request = response::create_request(&config.model, config.max_tokens, messages)?;
+        // single message, appended to a loaded conversation
+        let mut prefix: Vec<_> = conversation.messages.iter().take(1).cloned().collect();
+        let (pinned, warnings) = ContextSet::from_references(conversation.context.clone()).read();
+        for warning in warnings {
+            eprintln!("{} {}", "Warning:".yellow(), warning);
+        }
+        if !pinned.is_empty() {
+            prefix.push(utils::new_system_message(ContextSet::to_message(&pinned)));
+        }
+        let messages = response::fit_to_context_window(
+            &backend,
+            &config,
+            &mut conversation,
+            &prefix,
+            &config.model,
+            config.max_tokens,
+            |notice| eprintln!("{}", notice),
+        )
+        .await;
+        let sampling = conversation
+            .sampling
+            .clone()
+            .unwrap_or_else(|| config.sampling.clone());
+        let request = response::create_request(&config.model, config.max_tokens, messages)
+            .with_reasoning_effort(config.reasoning_effort.clone())
+            .with_sampling(sampling.clone());
         let mut highlighter = if config.syntax_highlighting {
             Some(render::Highlighter::new(&config.theme)?)
         } else {
             None
         };
-        response::stream_response(&client, request, &mut highlighter, config.wrap_width).await?;
+        let retry = retry::RetryPolicy::from_config(&config);
+        let response = response::stream_response(
+            &backend,
+            request,
+            &mut highlighter,
+            config.wrap_width,
+            &retry,
+        )
+        .await?;
+        if response.interrupted {
+            conversation.add_truncated_assistant_message(response.text);
+        } else {
+            conversation.add_assistant_message(response.text);
+        }
+        if let Some(usage) = response.usage {
+            let usage = usage::TurnUsage::new(&config, &config.model, usage);
+            // on stderr, so that it isn't mixed into piped output
+            if config.usage_footer && std::io::stderr().is_terminal() {
+                eprintln!("\n{}", usage.footer().dim());
+            }
+            if config.usage_ledger
+                && let Err(e) = usage::record(&usage)
+            {
+                eprintln!("{} Failed to record usage: {}", "Warning:".yellow(), e);
+            }
+            conversation.record_usage(usage);
+        }
+        if resuming {
+            conversation.updated = chrono::Local::now();
+            conversation.model = Some(config.model.clone());
+            conversation.profile = config.profile.clone();
+            conversation.sampling = Some(sampling);
+            session::save(&config, &conversation, file.as_deref())
+                .context("Failed to save the conversation")?;
+        }
     }
     Ok(())
 }
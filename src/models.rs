@@ -0,0 +1,187 @@
+/* -------------------------------------------------------------------------- */
+/*                                 models.rs                                  */
+/* -------------------------------------------------------------------------- */
+
+// Known model families and their capabilities. Anything that can't be inferred
+// from the model name can be overridden in a `[models."<name>"]` config table.
+
+use std::collections::HashMap;
+use std::fs;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+
+use crate::backend::{Backend, ChatBackend};
+use crate::config::{Config, get_model_cache_path};
+use crate::response::interruptible;
+
+/// Models that think before answering: OpenAI o-series and gpt-5, and Claude
+/// models with extended thinking.
+const REASONING_MODEL_PREFIXES: [&str; 8] = [
+    "o1",
+    "o3",
+    "o4",
+    "gpt-5",
+    "claude-3-7",
+    "claude-sonnet-4",
+    "claude-opus-4",
+    "claude-haiku-4",
+];
+
+pub fn is_reasoning_model(model: &str) -> bool {
+    // strip a provider/namespace prefix such as "openai/o3"
+    let name = model.rsplit('/').next().unwrap_or(model);
+    REASONING_MODEL_PREFIXES
+        .iter()
+        .any(|prefix| name.starts_with(prefix))
+        && !name.contains("-chat")
+}
+
+/* -------------------------------------------------------------------------- */
+/*                               Context windows                              */
+/* -------------------------------------------------------------------------- */
+
+/// Context window sizes in tokens, matched by the longest model name prefix.
+const CONTEXT_WINDOWS: [(&str, u32); 14] = [
+    ("gpt-3.5-turbo", 16_385),
+    ("gpt-4", 8_192),
+    ("gpt-4-32k", 32_768),
+    ("gpt-4-turbo", 128_000),
+    ("gpt-4o", 128_000),
+    ("gpt-4.1", 1_047_576),
+    ("gpt-5", 400_000),
+    ("o1", 200_000),
+    ("o1-mini", 128_000),
+    ("o3", 200_000),
+    ("o4", 200_000),
+    ("claude", 200_000),
+    ("llama3.1", 128_000),
+    ("llama3.2", 128_000),
+];
+
+/// Assumed for models that aren't in the table, which are often local models
+/// with small windows. Set `context_window` in the model's table to override.
+const DEFAULT_CONTEXT_WINDOW: u32 = 8_192;
+
+/// Returns the context window of a model (or alias) in tokens.
+pub fn context_window(config: &Config, model: &str) -> u32 {
+    let model = config.aliases.get(model).map_or(model, String::as_str);
+    if let Some(window) = config.models.get(model).and_then(|m| m.context_window) {
+        return window;
+    }
+    let name = model.rsplit('/').next().unwrap_or(model);
+    CONTEXT_WINDOWS
+        .iter()
+        .filter(|(prefix, _)| name.starts_with(prefix))
+        .max_by_key(|(prefix, _)| prefix.len())
+        .map_or(DEFAULT_CONTEXT_WINDOW, |(_, window)| *window)
+}
+
+/* -------------------------------------------------------------------------- */
+/*                                 Model list                                 */
+/* -------------------------------------------------------------------------- */
+
+/// Offered when the provider's model list can't be fetched and isn't cached.
+const FALLBACK_MODELS: [&str; 6] = [
+    "gpt-3.5-turbo",
+    "gpt-4o",
+    "gpt-4o-mini",
+    "gpt-4.1",
+    "gpt-5",
+    "o3",
+];
+
+#[derive(Serialize, Deserialize, Default)]
+struct ModelCache {
+    entries: HashMap<String, CacheEntry>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct CacheEntry {
+    /// Unix timestamp (seconds) of when the list was fetched.
+    fetched_at: u64,
+    models: Vec<String>,
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or_default()
+}
+
+fn read_cache() -> ModelCache {
+    get_model_cache_path()
+        .ok()
+        .and_then(|path| fs::read_to_string(path).ok())
+        .and_then(|data| serde_json::from_str(&data).ok())
+        .unwrap_or_default()
+}
+
+fn write_cache(key: &str, models: &[String]) -> Result<()> {
+    let mut cache = read_cache();
+    cache.entries.insert(
+        key.to_string(),
+        CacheEntry {
+            fetched_at: now(),
+            models: models.to_vec(),
+        },
+    );
+    fs::write(
+        get_model_cache_path()?,
+        serde_json::to_string_pretty(&cache)?,
+    )?;
+    Ok(())
+}
+
+/// Lists the models offered by the backend, followed by the configured aliases.
+///
+/// The provider's list is cached on disk for `ttl` seconds (0 disables the
+/// cache). If it can't be fetched, a stale cached list or the built-in
+/// fallback list is used instead.
+pub async fn fetch_models(
+    backend: &Backend,
+    aliases: &HashMap<String, String>,
+    ttl: u64,
+) -> Vec<String> {
+    let cached = read_cache().entries.remove(backend.cache_key());
+    let models = match cached {
+        Some(entry) if ttl > 0 && now().saturating_sub(entry.fetched_at) < ttl => entry.models,
+        stale => match interruptible(backend.list_models()).await {
+            Ok(models) if !models.is_empty() => {
+                if ttl > 0 {
+                    // a cache that can't be written only costs a refetch next time
+                    let _ = write_cache(backend.cache_key(), &models);
+                }
+                models
+            }
+            _ => match stale {
+                Some(entry) => entry.models,
+                None => fallback_models(),
+            },
+        },
+    };
+    with_aliases(models, aliases)
+}
+
+/// Like [`fetch_models`], but never touches the network, so it can be used
+/// at startup. Stale cache entries are accepted.
+pub fn cached_models(backend: &Backend, aliases: &HashMap<String, String>) -> Vec<String> {
+    let models = match read_cache().entries.remove(backend.cache_key()) {
+        Some(entry) => entry.models,
+        None => fallback_models(),
+    };
+    with_aliases(models, aliases)
+}
+
+fn fallback_models() -> Vec<String> {
+    FALLBACK_MODELS.iter().map(|m| m.to_string()).collect()
+}
+
+fn with_aliases(models: Vec<String>, aliases: &HashMap<String, String>) -> Vec<String> {
+    let mut names: Vec<String> = aliases.keys().cloned().collect();
+    names.sort();
+    names.extend(models.into_iter().filter(|m| !aliases.contains_key(m)));
+    names
+}
@@ -33,6 +33,31 @@ pub fn animations_enabled() -> bool {
     ANIMATIONS_ENABLED.load(Ordering::Relaxed)
 }
 
+/* -------------------------------------------------------------------------- */
+/*                           Terminal Output Control                          */
+/* -------------------------------------------------------------------------- */
+
+// When stdout is piped, only the response text goes to stdout: no cursor
+// escapes, no reasoning, and notices are written to stderr instead.
+static TERMINAL_OUTPUT: AtomicBool = AtomicBool::new(true);
+
+pub fn set_terminal_output(enabled: bool) {
+    TERMINAL_OUTPUT.store(enabled, Ordering::Relaxed);
+}
+
+pub fn terminal_output() -> bool {
+    TERMINAL_OUTPUT.load(Ordering::Relaxed)
+}
+
+/// Prints a status message to stdout, or to stderr when stdout is piped.
+pub fn print_notice(text: &str) {
+    if terminal_output() {
+        snailprint(text, 2000);
+    } else {
+        eprint!("{}", text);
+    }
+}
+
 /* -------------------------------------------------------------------------- */
 
 pub struct Highlighter {
@@ -45,10 +70,20 @@ pub struct Highlighter {
 const SYNTAX_SET: &[u8] = include_bytes!("../syntax_set.bin");
 // const SYNTAX_SET: &[u8] = include_bytes!(concat!(env!("OUT_DIR"), "/syntax_set.bin"));
 
+/// Loads the syntax definitions used for highlighting, in the terminal and in
+/// exported conversations.
+pub fn load_syntax_set() -> Result<SyntaxSet> {
+    dumps::from_uncompressed_data(SYNTAX_SET).context("Failed to load syntax set")
+}
+
+/// Whether a theme uses terminal palette indices rather than colors.
+pub fn is_ansi_theme(theme_name: &str) -> bool {
+    theme_name == "ansi" || theme_name == "base16" || theme_name == "base16-256"
+}
+
 impl Highlighter {
     pub fn new(theme_name: &str) -> Result<Self> {
-        let ss: SyntaxSet =
-            dumps::from_uncompressed_data(SYNTAX_SET).context("Failed to load syntax set")?;
+        let ss = load_syntax_set()?;
 
         let syntax_ref = ss
             .find_syntax_by_name("Markdown")
@@ -59,7 +94,7 @@ impl Highlighter {
 
         // highlighting_assets will live as long as the struct
         let theme_static: &'static _ = unsafe { std::mem::transmute(theme) };
-        let highlighter = HighlightLines::new(&syntax_ref, theme_static);
+        let highlighter = HighlightLines::new(syntax_ref, theme_static);
 
         Ok(Highlighter {
             _highlighting_assets: highlighting_assets,
@@ -69,10 +104,15 @@ impl Highlighter {
         })
     }
 
+    /// Starts highlighting from scratch, forgetting any open code block.
+    pub fn reset(&mut self) {
+        if let Ok(highlighter) = Highlighter::new(&self.theme_name) {
+            *self = highlighter;
+        }
+    }
+
     pub fn highlight_line(&mut self, line: &str) -> String {
-        let theme_is_ansi = &self.theme_name == "ansi"
-            || &self.theme_name == "base16"
-            || &self.theme_name == "base16-256";
+        let theme_is_ansi = is_ansi_theme(&self.theme_name);
 
         let ranges = match self.highlighter.highlight_line(line, &self.syntax_set) {
             Ok(ranges) => ranges,
@@ -130,6 +170,11 @@ pub fn render_line_plain(line: &str, wrap_width: u32) -> Result<()> {
     Ok(())
 }
 
+pub fn render_reasoning_line(line: &str, wrap_width: u32) {
+    let line = wrap_line(line, wrap_width);
+    snailprint(&line.dim().italic().to_string(), 1000);
+}
+
 pub fn snailprint(text: &str, num_micros: u64) {
     if !animations_enabled() {
         print!("{}", text);
@@ -6,9 +6,8 @@ use std::thread::sleep;
 use std::time::Duration;
 
 use anyhow::{Context, Result, bail};
-use async_openai::Client;
-use async_openai::config::OpenAIConfig;
-use async_openai::types::{ChatCompletionRequestMessage, CreateChatCompletionRequest};
+use async_openai::types::ChatCompletionRequestMessage;
+use dialoguer::{Select, theme::ColorfulTheme};
 use yansi::Paint;
 
 #[derive(Debug, PartialEq)]
@@ -16,21 +15,37 @@ enum LoopControl {
     Continue,
     Exit,
 }
+use crate::backend::{Backend, ChatRequest, Sampling};
 use crate::commands::Command;
 use crate::config::Config;
+use crate::context::{ContextFile, ContextSet, attach_files, collect_files};
 use crate::conversation::Conversation;
 use crate::editor::{Editor, EditorConfig, Input};
+use crate::export::{Format, export};
+use crate::models::{cached_models, fetch_models};
 use crate::render::{Highlighter, snailprint};
-use crate::response::{create_request, generate_title, stream_response};
+use crate::response::{
+    StreamedResponse, create_request, fit_to_context_window, generate_title, stream_response,
+};
+use crate::retry::RetryPolicy;
+use crate::session;
+use crate::store::{Store, print_results};
+use crate::tokens::count_tokens;
+use crate::usage::{TurnUsage, print_totals, record};
 use crate::utils::{
-    clear_console, print_help, print_sample_text, print_separator, select_filename,
-    select_json_file, select_model, select_theme,
+    clear_console, new_system_message, print_help, print_sample_text, print_separator,
+    select_filename, select_json_file, select_model, select_profile, select_theme,
+    title_to_filename,
 };
 
 pub struct ReadEvalPrintLoop {
-    client: Client<OpenAIConfig>,
+    config: Config,
+    backend: Backend,
     model: String,
+    title_model: Option<String>,
     max_tokens: u32,
+    reasoning_effort: Option<String>,
+    sampling: Sampling,
     conversation: Conversation,
     editor: Editor,
     theme: String,
@@ -39,56 +54,49 @@ pub struct ReadEvalPrintLoop {
     greetings: bool,
     wrap_width: u32,
     conversations_folder: String,
+    /// Files from `--context` to attach to the next message.
+    pending_context: Vec<String>,
+    /// Files pinned with `/context`, sent with every request.
+    context: ContextSet,
+    /// Usage of the responses received in this session.
+    session_usage: Vec<TurnUsage>,
+    /// Ids of the conversations found by the last `/search`, for `/load <n>`.
+    search_results: Vec<String>,
 }
 
 impl ReadEvalPrintLoop {
-    pub fn new(client: Client<OpenAIConfig>, config: Config) -> Self {
-        let editor_config = EditorConfig::from_config(&config);
-        let editor = Editor::new(editor_config);
-        let model = config.model;
-        let max_tokens = config.max_tokens;
-        let conversation = Conversation::new(config.system_prompt);
-        let theme = config.theme;
-        let syntax_highlighting = config.syntax_highlighting;
-        let history_file = None;
-        let greetings = config.greetings;
-        let wrap_width = config.wrap_width;
-        let conversations_folder = config.conversations_folder;
-        Self {
-            client,
-            model,
-            max_tokens,
-            conversation,
-            editor,
-            theme,
-            syntax_highlighting,
-            history_file,
-            greetings,
-            wrap_width,
-            conversations_folder,
-        }
-    }
-
     pub fn with_conversation(
-        client: Client<OpenAIConfig>,
+        backend: Backend,
         config: Config,
         conversation: Conversation,
-        // history_file: Option<String>,
+        history_file: Option<String>,
     ) -> Self {
         let editor_config = EditorConfig::from_config(&config);
         let editor = Editor::new(editor_config);
-        let model = config.model;
+        editor.set_models(cached_models(&backend, &config.aliases));
+        let model = config.model.clone();
+        let title_model = config.title_model.clone();
         let max_tokens = config.max_tokens;
-        let theme = config.theme;
+        let reasoning_effort = config.reasoning_effort.clone();
+        // a loaded conversation continues with the parameters it was saved with
+        let sampling = conversation
+            .sampling
+            .clone()
+            .unwrap_or_else(|| config.sampling.clone());
+        let theme = config.theme.clone();
         let syntax_highlighting = config.syntax_highlighting;
-        let history_file = None;
         let greetings = config.greetings;
         let wrap_width = config.wrap_width;
-        let conversations_folder = config.conversations_folder;
+        let conversations_folder = config.conversations_folder.clone();
+        let context = ContextSet::from_references(conversation.context.clone());
         Self {
-            client,
+            config,
+            backend,
             model,
+            title_model,
             max_tokens,
+            reasoning_effort,
+            sampling,
             conversation,
             editor,
             theme,
@@ -97,28 +105,36 @@ impl ReadEvalPrintLoop {
             greetings,
             wrap_width,
             conversations_folder,
+            pending_context: Vec::new(),
+            context,
+            session_usage: Vec::new(),
+            search_results: Vec::new(),
         }
     }
 
+    pub fn attach_to_next_message(&mut self, paths: Vec<String>) {
+        self.pending_context = paths;
+    }
+
     pub async fn run(&mut self) -> Result<()> {
         self.check_conversations_folder_on_startup();
 
-        match self.conversation.messages.last().unwrap() {
-            ChatCompletionRequestMessage::User(_) => {
-                let request = create_request(
-                    &self.model,
-                    self.max_tokens,
-                    self.conversation.messages.clone(),
-                )?;
-                let response = match self.get_response(request).await {
+        match self.conversation.messages.last() {
+            Some(ChatCompletionRequestMessage::User(_)) => {
+                let request = self.create_request().await;
+                match self.get_response(request).await {
                     Err(e) => {
                         snailprint(&format!("\n{} {}\n\n", "Error:".red(), e), 5000);
-                        String::new()
+                        self.conversation.add_assistant_message(String::new());
                     }
-                    Ok(response) => response,
-                };
-                self.conversation.add_assistant_message(response);
+                    Ok(response) => {
+                        self.add_response(response);
+                        self.auto_save().await;
+                    }
+                }
             }
+            // a resumed conversation is shown as it was left
+            _ if self.conversation.messages.len() > 1 => self.print_conversation(),
             _ => {
                 if self.greetings {
                     snailprint("\nHey!\n\n", 10000);
@@ -131,22 +147,14 @@ impl ReadEvalPrintLoop {
             let input = self.editor.get_input();
             match input {
                 Input::Message(message) => {
-                    self.conversation.add_user_message(message);
-                    let request = create_request(
-                        &self.model,
-                        self.max_tokens,
-                        self.conversation.messages.clone(),
-                    )?;
-                    let response = match self.get_response(request).await {
-                        Err(e) => {
-                            snailprint(&format!("\n{} {}\n\n", "Error:".red(), e), 5000);
-                            continue;
-                        }
-                        Ok(response) => response,
-                    };
-                    self.conversation.add_assistant_message(response);
+                    let content = self.attach_files(&message);
+                    self.conversation
+                        .add_user_message_with_files(message, content);
+                    if let Err(e) = self.respond().await {
+                        snailprint(&format!("\n{} {}\n\n", "Error:".red(), e), 5000);
+                    }
                 }
-                Input::Command(command) => match self.handle_command(command).await? {
+                Input::Command(command, args) => match self.handle_command(command, args).await? {
                     LoopControl::Exit => break,
                     LoopControl::Continue => {}
                 },
@@ -156,7 +164,7 @@ impl ReadEvalPrintLoop {
         Ok(())
     }
 
-    async fn handle_command(&mut self, command: Command) -> Result<LoopControl> {
+    async fn handle_command(&mut self, command: Command, args: Vec<String>) -> Result<LoopControl> {
         match command {
             Command::Exit => {
                 if self.greetings {
@@ -178,11 +186,28 @@ impl ReadEvalPrintLoop {
                 );
                 sleep(Duration::from_millis(500));
                 self.conversation.reset();
+                // the reset conversation is saved as a new one
+                self.history_file = None;
             }
-            Command::SelectModel => {
-                let selection = select_model(&self.model)?;
-                self.model = selection;
-            }
+            Command::SelectModel => match args.first() {
+                Some(model) => {
+                    self.model = model.clone();
+                    snailprint(
+                        &format!("\n{} {}\n\n", "Switched to model".green(), model.cyan()),
+                        2000,
+                    );
+                }
+                None => {
+                    let models = fetch_models(
+                        &self.backend,
+                        &self.config.aliases,
+                        self.config.model_cache_ttl,
+                    )
+                    .await;
+                    self.editor.set_models(models.clone());
+                    self.model = select_model(&models, &self.model)?;
+                }
+            },
             Command::SelectTheme => {
                 let selection = select_theme()?;
                 self.theme = selection;
@@ -190,6 +215,31 @@ impl ReadEvalPrintLoop {
                     snailprint(&format!("\n{} {}\n\n", "Error:".red(), e), 5000);
                 }
             }
+            Command::Profile => {
+                if let Err(e) = self.switch_profile(args.first()) {
+                    snailprint(&format!("\n{} {}\n\n", "Error:".red(), e), 5000);
+                }
+            }
+            Command::Set => match args.split_first() {
+                Some((name, values)) => {
+                    if let Err(e) = self.sampling.set(&name.to_lowercase(), values) {
+                        snailprint(&format!("\n{} {}\n\n", "Error:".red(), e), 5000);
+                    } else {
+                        self.print_sampling();
+                    }
+                }
+                None => self.print_sampling(),
+            },
+            Command::Context => {
+                if let Err(e) = self.manage_context(&args) {
+                    snailprint(&format!("\n{} {}\n\n", "Error:".red(), e), 5000);
+                }
+            }
+            Command::Usage => {
+                println!();
+                print_totals("This session", &self.session_usage);
+                print_totals("This conversation", &self.conversation.usage());
+            }
             Command::Save => {
                 if self.conversation.messages.len() <= 1 {
                     snailprint(
@@ -199,18 +249,75 @@ impl ReadEvalPrintLoop {
                         ),
                         5000,
                     );
-                } else {
-                    self.save_conversation().await?;
+                } else if let Err(e) = self.save_conversation().await {
+                    snailprint(&format!("\n{} {:#}\n\n", "Error:".red(), e), 5000);
                 }
             }
-            Command::Load => {
-                self.load_conversation()?;
-                print_separator();
-                self.print_conversation();
+            Command::Search => {
+                if let Err(e) = self.search(&args) {
+                    snailprint(&format!("\n{} {:#}\n\n", "Error:".red(), e), 5000);
+                }
+            }
+            Command::Load => match self.load_conversation(&args) {
+                Ok(()) => {
+                    print_separator();
+                    self.print_conversation();
+                }
+                Err(e) => snailprint(&format!("\n{} {:#}\n\n", "Error:".red(), e), 5000),
+            },
+            Command::Export => {
+                if let Err(e) = self.export(&args).await {
+                    snailprint(&format!("\n{} {:#}\n\n", "Error:".red(), e), 5000);
+                }
             }
             Command::History => {
                 self.print_conversation();
             }
+            Command::Retry => {
+                if let Err(e) = self.retry(&args).await {
+                    snailprint(&format!("\n{} {:#}\n\n", "Error:".red(), e), 5000);
+                }
+            }
+            Command::Undo => match self.conversation.undo() {
+                Ok((question, kept)) => {
+                    snailprint(
+                        &format!(
+                            "\n{} {}{}\n",
+                            "Removed".green(),
+                            "> ".magenta(),
+                            preview(&question)
+                        ),
+                        2000,
+                    );
+                    if kept {
+                        snailprint(
+                            &format!(
+                                "{}\n",
+                                "Messages on other branches are kept; use /branches to see them."
+                                    .dim()
+                            ),
+                            2000,
+                        );
+                    }
+                    println!();
+                    self.auto_save().await;
+                }
+                Err(e) => snailprint(&format!("\n{} {:#}\n\n", "Error:".red(), e), 5000),
+            },
+            Command::Edit => {
+                if let Err(e) = self.edit_message(&args).await {
+                    snailprint(&format!("\n{} {:#}\n\n", "Error:".red(), e), 5000);
+                }
+            }
+            Command::Branches => self.print_branches(),
+            Command::Switch => match self.switch_branch(&args) {
+                Ok(()) => {
+                    print_separator();
+                    self.print_conversation();
+                    self.auto_save().await;
+                }
+                Err(e) => snailprint(&format!("\n{} {:#}\n\n", "Error:".red(), e), 5000),
+            },
             Command::Help => {
                 print_help();
             }
@@ -227,16 +334,422 @@ impl ReadEvalPrintLoop {
         Ok(LoopControl::Continue)
     }
 
-    pub async fn get_response(&mut self, request: CreateChatCompletionRequest) -> Result<String> {
+    /// Sends the conversation and adds the response to it.
+    async fn respond(&mut self) -> Result<()> {
+        let request = self.create_request().await;
+        let response = self.get_response(request).await?;
+        self.add_response(response);
+        self.auto_save().await;
+        Ok(())
+    }
+
+    /// Answers the last user message again, optionally with another model
+    /// and temperature (`/retry [model] [temperature]`) for this response
+    /// only. The earlier answers are kept as other versions of it.
+    async fn retry(&mut self, args: &[String]) -> Result<()> {
+        let mut model = self.model.clone();
+        let mut sampling = self.sampling.clone();
+        for arg in args {
+            match arg.parse::<f32>() {
+                Ok(temperature) => sampling.temperature = Some(temperature),
+                Err(_) => model = arg.clone(),
+            }
+        }
+        sampling.validate()?;
+        let leaf = self.conversation.retry()?;
+        if !args.is_empty() {
+            let mut details = model.cyan().to_string();
+            if let Some(temperature) = sampling.temperature {
+                details.push_str(&format!(" (temperature {})", temperature));
+            }
+            snailprint(
+                &format!("\n{} {}\n", "Retrying with".green(), details),
+                2000,
+            );
+        }
+        let model = std::mem::replace(&mut self.model, model);
+        let sampling = std::mem::replace(&mut self.sampling, sampling);
+        let request = self.create_request().await;
+        let result = self
+            .get_response(request)
+            .await
+            .map(|response| self.add_response(response));
+        self.model = model;
+        self.sampling = sampling;
+        if result.is_err() {
+            // keep showing the answer that was there
+            self.conversation.restore(leaf);
+        }
+        result?;
+        self.auto_save().await;
+        Ok(())
+    }
+
+    /// Rewrites a user message (the `n`th of the active branch, or the last)
+    /// on a new branch and responds to it. Without new text, the message is
+    /// edited in place.
+    async fn edit_message(&mut self, args: &[String]) -> Result<()> {
+        let questions: Vec<usize> = self
+            .conversation
+            .messages
+            .iter()
+            .enumerate()
+            .filter(|(_, message)| matches!(message, ChatCompletionRequestMessage::User(_)))
+            .map(|(i, _)| i)
+            .collect();
+        if questions.is_empty() {
+            bail!("There are no messages to edit.");
+        }
+        let (number, words) = match args.split_first() {
+            Some((first, rest)) if first.parse::<usize>().is_ok() => {
+                (first.parse::<usize>()?, rest)
+            }
+            _ => (questions.len(), args),
+        };
+        let index = *questions.get(number.wrapping_sub(1)).with_context(|| {
+            format!(
+                "There is no message {} (the conversation has {})",
+                number,
+                questions.len()
+            )
+        })?;
+        let text = if words.is_empty() {
+            println!();
+            dialoguer::Input::<String>::with_theme(&ColorfulTheme::default())
+                .with_prompt("Edit message")
+                .with_initial_text(self.conversation.typed_text(index))
+                .interact_text()
+                .context("Failed to read input")?
+        } else {
+            words.join(" ")
+        };
+        // only the references typed are attached again
+        let content = self.attach_files(&text);
+        self.conversation.edit(index, text, content)?;
+        print_separator();
+        self.print_conversation();
+        self.respond().await
+    }
+
+    fn print_branches(&self) {
+        let branches = self.conversation.branches();
+        if branches.len() <= 1 {
+            snailprint(
+                &format!(
+                    "\n{} Use /{} to rewrite a message on a new branch.\n\n",
+                    "No other branches.".dim(),
+                    "edit".cyan()
+                ),
+                2000,
+            );
+            return;
+        }
+        snailprint(&format!("\n{}\n", "Branches:".bold()), 1000);
+        for (i, branch) in branches.iter().enumerate() {
+            let details = if branch.active {
+                format!("({} messages)", branch.length)
+            } else {
+                format!(
+                    "({} messages, differs from message {})",
+                    branch.length,
+                    branch.shared + 1
+                )
+            };
+            let mut line = format!(
+                "{} {}{} {}",
+                format!("{}.", i + 1).cyan(),
+                "> ".magenta(),
+                preview(&branch.question),
+                details.dim()
+            );
+            if branch.active {
+                line.push_str(&format!(" {}", "active".green()));
+            }
+            snailprint(&format!("{}\n", line), 1000);
+            if let Some(answer) = &branch.answer {
+                snailprint(&format!("   {}\n", preview(answer).dim()), 1000);
+            }
+        }
+        snailprint(
+            &format!(
+                "\n{}\n\n",
+                format!("Use /{} <number> to continue a branch.", "switch".cyan()).dim()
+            ),
+            2000,
+        );
+    }
+
+    /// Makes another branch active, given by number or selected from a list.
+    fn switch_branch(&mut self, args: &[String]) -> Result<()> {
+        let number = match args.first() {
+            Some(arg) => arg
+                .parse::<usize>()
+                .with_context(|| format!("Invalid branch number '{}'", arg))?,
+            None => {
+                let branches = self.conversation.branches();
+                if branches.len() <= 1 {
+                    bail!("There are no other branches.");
+                }
+                let labels: Vec<String> = branches
+                    .iter()
+                    .map(|branch| preview(&branch.question))
+                    .collect();
+                let active = branches.iter().position(|branch| branch.active);
+                println!();
+                let selection = Select::with_theme(&ColorfulTheme::default())
+                    .with_prompt("Select a branch")
+                    .items(&labels)
+                    .default(active.unwrap_or(0))
+                    .interact_opt()
+                    .context("Failed to read input")?;
+                match selection {
+                    Some(i) => i + 1,
+                    None => bail!("Switch cancelled."),
+                }
+            }
+        };
+        self.conversation.switch(number)
+    }
+
+    /// Attaches the files referenced with `@path` (and any pending `--context`
+    /// files) to the message, reporting what was attached.
+    fn attach_files(&mut self, message: &str) -> String {
+        let pending = std::mem::take(&mut self.pending_context);
+        let (files, warnings) = collect_files(message, &pending);
+        let mut notes: Vec<String> = warnings
+            .iter()
+            .map(|warning| format!("{} {}", "Warning:".yellow(), warning))
+            .collect();
+        if !files.is_empty() {
+            let paths = files
+                .iter()
+                .map(|file| file.path.as_str())
+                .collect::<Vec<_>>()
+                .join(", ");
+            notes.push(format!("{} {}", "Attached".dim(), paths.blue()));
+        }
+        if !notes.is_empty() {
+            snailprint(&format!("\n{}\n", notes.join("\n")), 2000);
+        }
+        attach_files(message, &files)
+    }
+
+    /// Builds a request for the conversation so far. Pinned files are read
+    /// again and sent after the system prompt, and old turns are summarized
+    /// or dropped if the conversation no longer fits in the context window.
+    async fn create_request(&mut self) -> ChatRequest {
+        let mut prefix: Vec<_> = self.conversation.messages.iter().take(1).cloned().collect();
+        if !self.context.is_empty() {
+            let (files, warnings) = self.context.read();
+            for warning in warnings {
+                snailprint(&format!("\n{} {}\n", "Warning:".yellow(), warning), 2000);
+            }
+            if !files.is_empty() {
+                prefix.push(new_system_message(ContextSet::to_message(&files)));
+            }
+        }
+        let messages = fit_to_context_window(
+            &self.backend,
+            &self.config,
+            &mut self.conversation,
+            &prefix,
+            &self.model,
+            self.max_tokens,
+            |notice| snailprint(&format!("\n{}\n", notice), 2000),
+        )
+        .await;
+        create_request(&self.model, self.max_tokens, messages)
+            .with_reasoning_effort(self.reasoning_effort.clone())
+            .with_sampling(self.sampling.clone())
+    }
+
+    fn manage_context(&mut self, args: &[String]) -> Result<()> {
+        let (action, references) = match args.split_first() {
+            Some((action, references)) => (action.to_lowercase(), references),
+            None => ("list".to_string(), &[][..]),
+        };
+        match action.as_str() {
+            "add" => {
+                if references.is_empty() {
+                    bail!("Usage: /context add <path>...");
+                }
+                for reference in references {
+                    match self.context.add(reference) {
+                        Ok(files) => snailprint(
+                            &format!(
+                                "\n{} {} {}",
+                                "Pinned".green(),
+                                reference.blue(),
+                                format_cost(&self.model, &files).dim()
+                            ),
+                            2000,
+                        ),
+                        Err(e) => snailprint(&format!("\n{} {}", "Warning:".yellow(), e), 2000),
+                    }
+                }
+                println!("\n");
+            }
+            "remove" | "rm" => {
+                if references.is_empty() {
+                    bail!("Usage: /context remove <path or number>...");
+                }
+                for reference in references {
+                    let removed = self.context.remove(reference)?;
+                    snailprint(
+                        &format!("\n{} {}", "Unpinned".green(), removed.blue()),
+                        2000,
+                    );
+                }
+                println!("\n");
+            }
+            "clear" => {
+                self.context = ContextSet::default();
+                snailprint(&format!("\n{}\n\n", "Unpinned all files.".green()), 2000);
+            }
+            "list" | "ls" => self.print_context(),
+            _ => bail!(
+                "Unknown action '{}', expected add, remove, list or clear",
+                action
+            ),
+        }
+        Ok(())
+    }
+
+    fn print_context(&self) {
+        if self.context.is_empty() {
+            snailprint(
+                &format!(
+                    "\nNo pinned files. Pin one with /{}.\n\n",
+                    "context add <path>".cyan()
+                ),
+                2000,
+            );
+            return;
+        }
+        println!();
+        let mut total = 0;
+        for (i, reference) in self.context.references().iter().enumerate() {
+            let (files, warnings) = collect_files("", std::slice::from_ref(reference));
+            total += files
+                .iter()
+                .map(|f| count_tokens(&self.model, &f.content))
+                .sum::<usize>();
+            let detail = match warnings.first() {
+                Some(warning) if files.is_empty() => warning.yellow().to_string(),
+                _ => format_cost(&self.model, &files).dim().to_string(),
+            };
+            snailprint(
+                &format!(
+                    "{} {} {}\n",
+                    format!("{}.", i + 1).bold(),
+                    reference.blue(),
+                    detail
+                ),
+                1000,
+            );
+        }
+        snailprint(
+            &format!("{} ~{} tokens per request\n\n", "Total:".bold(), total),
+            1000,
+        );
+    }
+
+    fn add_response(&mut self, response: StreamedResponse) {
+        if response.interrupted {
+            self.conversation
+                .add_truncated_assistant_message(response.text);
+        } else {
+            self.conversation.add_assistant_message(response.text);
+        }
+        let Some(usage) = response.usage else {
+            return;
+        };
+        let usage = TurnUsage::new(&self.config, &self.model, usage);
+        if self.config.usage_footer {
+            snailprint(&format!("{}\n\n", usage.footer().dim()), 1000);
+        }
+        if self.config.usage_ledger
+            && let Err(e) = record(&usage)
+        {
+            snailprint(
+                &format!("{} Failed to record usage: {}\n\n", "Warning:".yellow(), e),
+                2000,
+            );
+        }
+        self.conversation.record_usage(usage.clone());
+        self.session_usage.push(usage);
+    }
+
+    pub async fn get_response(&mut self, request: ChatRequest) -> Result<StreamedResponse> {
         let mut highlighter = if self.syntax_highlighting {
             Some(Highlighter::new(&self.theme)?)
         } else {
             None
         };
-        stream_response(&self.client, request, &mut highlighter, self.wrap_width).await
+        let retry = RetryPolicy::from_config(&self.config);
+        stream_response(
+            &self.backend,
+            request,
+            &mut highlighter,
+            self.wrap_width,
+            &retry,
+        )
+        .await
+    }
+
+    fn switch_profile(&mut self, name: Option<&String>) -> Result<()> {
+        let name = match name {
+            Some(name) => name.clone(),
+            None => {
+                let profiles = self.config.profile_names();
+                if profiles.is_empty() {
+                    bail!("No profiles defined in the config file.");
+                }
+                select_profile(&profiles, self.config.profile.as_deref())?
+            }
+        };
+        let config = self.config.with_profile(&name)?;
+        self.backend = Backend::from_config(&config)?;
+        self.editor
+            .set_models(cached_models(&self.backend, &config.aliases));
+        self.model = config.model.clone();
+        self.max_tokens = config.max_tokens;
+        self.reasoning_effort = config.reasoning_effort.clone();
+        self.sampling = config.sampling.clone();
+        self.conversation
+            .set_system_prompt(config.system_prompt.clone());
+        self.config = config;
+        snailprint(
+            &format!(
+                "\n{} {} ({})\n\n",
+                "Switched to profile".green(),
+                name.blue(),
+                self.model.cyan()
+            ),
+            2000,
+        );
+        Ok(())
     }
 
-    async fn save_conversation(&self) -> Result<()> {
+    fn print_sampling(&self) {
+        println!();
+        for (name, value) in self.sampling.values() {
+            let value = match value {
+                Some(value) => value.cyan().to_string(),
+                None => "default".dim().to_string(),
+            };
+            snailprint(
+                &format!("{} {}\n", format!("{}:", name).bold(), value),
+                1000,
+            );
+        }
+        println!();
+    }
+
+    async fn save_conversation(&mut self) -> Result<()> {
+        if self.config.sqlite_store {
+            return self.save_to_store().await;
+        }
         let conversations_folder = match self.get_conversations_folder_for_operation() {
             Ok(folder) => folder,
             Err(_) => return Ok(()),
@@ -245,8 +758,10 @@ impl ReadEvalPrintLoop {
         let filename = if let Some(filename) = &self.history_file {
             filename.clone()
         } else {
+            let title_model = self.title_model.as_deref().unwrap_or(&self.model);
             let generated_title =
-                generate_title(&self.client, self.conversation.transcript()).await?;
+                generate_title(&self.backend, title_model, self.conversation.transcript()).await?;
+            self.conversation.title = Some(generated_title.clone());
             select_filename(generated_title)?
         };
 
@@ -259,6 +774,7 @@ impl ReadEvalPrintLoop {
                 .with_context(|| "Failed to create conversations directory")?;
         }
 
+        self.update_conversation();
         self.conversation.save_to_json_file(&filepath_str)?;
         snailprint(
             &format!(
@@ -271,23 +787,193 @@ impl ReadEvalPrintLoop {
         Ok(())
     }
 
-    fn load_conversation(&mut self) -> Result<()> {
-        let conversations_folder = match self.get_conversations_folder_for_operation() {
-            Ok(folder) => folder,
-            Err(_) => return Ok(()),
+    async fn save_to_store(&mut self) -> Result<()> {
+        let mut store = Store::open(&self.config)?;
+        let title = match &self.conversation.title {
+            Some(title) => title.clone(),
+            None => {
+                let title_model = self.title_model.as_deref().unwrap_or(&self.model);
+                generate_title(&self.backend, title_model, self.conversation.transcript()).await?
+            }
         };
+        self.conversation.title = Some(title.clone());
+        self.update_conversation();
+        store.save(&self.conversation)?;
+        snailprint(
+            &format!(
+                "\n{} {}.\n\n",
+                "Conversation saved as".green(),
+                title.blue()
+            ),
+            2000,
+        );
+        Ok(())
+    }
 
-        let filename = match select_json_file(&conversations_folder) {
-            Ok(None) => {
-                bail!("Load cancelled.");
+    /// Writes the conversation as a document (`/export [md|html|txt] [path]`).
+    /// Without a path, the file is named after the title, in the current
+    /// directory.
+    async fn export(&mut self, args: &[String]) -> Result<()> {
+        if self.conversation.messages.len() <= 1 {
+            bail!("No conversation history to export.");
+        }
+        let (format, path) = match args.split_first() {
+            Some((first, rest)) if Format::from_name(first).is_some() => {
+                (Format::from_name(first), rest)
             }
-            Ok(Some(filename)) => filename,
-            Err(e) => {
-                return Err(e);
+            _ => (None, args),
+        };
+        let path = (!path.is_empty()).then(|| path.join(" "));
+        let format = format
+            .or_else(|| path.as_deref().and_then(Format::from_path))
+            .unwrap_or(Format::Markdown);
+        let path = match path {
+            Some(path) => self.expand_path(&path)?,
+            None => {
+                if self.conversation.title.is_none() {
+                    let title_model = self.title_model.as_deref().unwrap_or(&self.model);
+                    self.conversation.title = Some(
+                        generate_title(&self.backend, title_model, self.conversation.transcript())
+                            .await?,
+                    );
+                }
+                let name = title_to_filename(self.conversation.title.as_deref().unwrap_or(""));
+                let name = name.strip_suffix(".json").unwrap_or(&name);
+                self.expand_path(&format!("{}.{}", name, format.extension()))?
             }
         };
-        self.history_file = Some(filename.clone());
-        self.conversation = Conversation::from_json_file(&filename)?;
+        let document = export(&self.conversation, format, &self.theme)?;
+        std::fs::write(&path, document).with_context(|| format!("Failed to write {}", path))?;
+        snailprint(
+            &format!("\n{} {}\n\n", "Exported to".green(), path.blue()),
+            2000,
+        );
+        Ok(())
+    }
+
+    /// Saves the session after a response, with `auto_save`.
+    async fn auto_save(&mut self) {
+        if !self.config.auto_save {
+            return;
+        }
+        if let Err(e) = self.save_session().await {
+            snailprint(
+                &format!(
+                    "{} Failed to save the session: {:#}\n\n",
+                    "Warning:".yellow(),
+                    e
+                ),
+                2000,
+            );
+        }
+    }
+
+    /// Saves the conversation to the store, or to a file in the conversations
+    /// folder named after its title, and records it as the last session.
+    async fn save_session(&mut self) -> Result<()> {
+        if self.conversation.title.is_none() {
+            let title_model = self.title_model.as_deref().unwrap_or(&self.model);
+            // without a title the file is named by date, and the title is
+            // tried again on the next save
+            self.conversation.title =
+                generate_title(&self.backend, title_model, self.conversation.transcript())
+                    .await
+                    .ok();
+        }
+        self.update_conversation();
+        let reference = if self.config.sqlite_store {
+            Store::open(&self.config)?.save(&self.conversation)?;
+            self.conversation.id.clone()
+        } else {
+            let filepath = match &self.history_file {
+                Some(filepath) => filepath.clone(),
+                None => self.new_session_file()?,
+            };
+            self.conversation.save_to_json_file(&filepath)?;
+            self.history_file = Some(filepath.clone());
+            filepath
+        };
+        session::record(&reference)
+    }
+
+    /// Picks a file for a new session, without overwriting other
+    /// conversations.
+    fn new_session_file(&self) -> Result<String> {
+        let folder = std::path::PathBuf::from(self.expand_path(&self.conversations_folder)?);
+        std::fs::create_dir_all(&folder)
+            .with_context(|| "Failed to create conversations directory")?;
+        let filename = title_to_filename(self.conversation.title.as_deref().unwrap_or_default());
+        let mut filepath = folder.join(&filename);
+        if filepath.exists() {
+            let stem = filename.trim_end_matches(".json");
+            filepath = folder.join(format!("{}_{}.json", stem, self.conversation.id));
+        }
+        Ok(filepath.to_string_lossy().to_string())
+    }
+
+    /// Records the session's state in the conversation before saving.
+    fn update_conversation(&mut self) {
+        self.conversation.updated = chrono::Local::now();
+        self.conversation.model = Some(self.model.clone());
+        self.conversation.profile = self.config.profile.clone();
+        self.conversation.sampling = Some(self.sampling.clone());
+        self.conversation.context = self.context.references().to_vec();
+    }
+
+    /// Loads a conversation given as a `/search` result number, a file or a
+    /// stored conversation id, or selected from a list when not given.
+    fn load_conversation(&mut self, args: &[String]) -> Result<()> {
+        let (conversation, history_file) = match args.first() {
+            Some(arg) => match arg.parse::<usize>() {
+                Ok(n) if (1..=self.search_results.len()).contains(&n) => {
+                    let store = Store::open(&self.config)?;
+                    (store.load(&self.search_results[n - 1])?, None)
+                }
+                _ => session::open(&self.config, arg)?,
+            },
+            None if self.config.sqlite_store => {
+                let store = Store::open(&self.config)?;
+                let Some(id) = select_stored_conversation(&store)? else {
+                    bail!("Load cancelled.");
+                };
+                (store.load(&id)?, None)
+            }
+            None => {
+                let conversations_folder = match self.get_conversations_folder_for_operation() {
+                    Ok(folder) => folder,
+                    Err(_) => bail!("Load cancelled."),
+                };
+                let Some(filename) = select_json_file(&conversations_folder)? else {
+                    bail!("Load cancelled.");
+                };
+                (Conversation::from_json_file(&filename)?, Some(filename))
+            }
+        };
+        self.history_file = history_file;
+        self.conversation = conversation;
+        if let Some(sampling) = &self.conversation.sampling {
+            self.sampling = sampling.clone();
+        }
+        self.context = ContextSet::from_references(self.conversation.context.clone());
+        Ok(())
+    }
+
+    fn search(&mut self, terms: &[String]) -> Result<()> {
+        if terms.is_empty() {
+            bail!("Usage: /search <terms>");
+        }
+        let results = Store::open(&self.config)?.search(terms)?;
+        print_results(&results);
+        if !results.is_empty() {
+            snailprint(
+                &format!(
+                    "{}\n\n",
+                    format!("Use /{} <number> to open a conversation.", "load".cyan()).dim()
+                ),
+                2000,
+            );
+        }
+        self.search_results = results.into_iter().map(|result| result.id).collect();
         Ok(())
     }
 
@@ -393,3 +1079,43 @@ impl ReadEvalPrintLoop {
         println!();
     }
 }
+
+/// Describes the files matched by a pinned reference and their token cost.
+fn format_cost(model: &str, files: &[ContextFile]) -> String {
+    let tokens: usize = files.iter().map(|f| count_tokens(model, &f.content)).sum();
+    match files {
+        [_] => format!("(~{} tokens)", tokens),
+        _ => format!("({} files, ~{} tokens)", files.len(), tokens),
+    }
+}
+
+/// Lets the user pick one of the most recent stored conversations.
+fn select_stored_conversation(store: &Store) -> Result<Option<String>> {
+    let conversations = store.recent()?;
+    if conversations.is_empty() {
+        println!("{}", "No saved conversations.".yellow());
+        return Ok(None);
+    }
+    let labels: Vec<String> = conversations.iter().map(|c| c.label()).collect();
+    println!();
+    let selection = Select::with_theme(&ColorfulTheme::default())
+        .with_prompt("Select a conversation to load")
+        .items(&labels)
+        .default(0)
+        .interact_opt()
+        .context("Failed to read input")?;
+    println!();
+    Ok(selection.map(|i| conversations[i].id.clone()))
+}
+
+/// The first line of a message, shortened to fit on one line.
+fn preview(text: &str) -> String {
+    const WIDTH: usize = 60;
+    let line = text.lines().next().unwrap_or_default();
+    if line.chars().count() > WIDTH || text.lines().nth(1).is_some() {
+        let shortened: String = line.chars().take(WIDTH).collect();
+        format!("{}…", shortened.trim_end())
+    } else {
+        line.to_string()
+    }
+}
@@ -1,14 +1,20 @@
-use anyhow::Result;
-use async_openai::types::{
-    ChatCompletionRequestMessage, CreateChatCompletionRequest, CreateChatCompletionRequestArgs,
-};
-use async_openai::{Client, config::OpenAIConfig};
+use anyhow::{Result, bail};
+use async_openai::types::ChatCompletionRequestMessage;
 use crossterm::cursor;
 use yansi::Paint;
 
 use futures_util::stream::StreamExt;
 
-use crate::render::{Highlighter, render_line, render_line_plain, snailprint};
+use crate::backend::{ChatBackend, ChatEvent, ChatRequest, Sampling, Usage};
+use crate::config::Config;
+use crate::conversation::{Conversation, Summary, format_transcript};
+use crate::models::{context_window, is_reasoning_model};
+use crate::render::{
+    Highlighter, print_notice, render_line, render_line_plain, render_reasoning_line, snailprint,
+    terminal_output,
+};
+use crate::retry::{RetryPolicy, wait_with_countdown};
+use crate::tokens::{ContextPolicy, count_message_tokens, truncate_to_fit};
 use crate::utils::{new_system_message, new_user_message};
 
 struct ResponseBuffer {
@@ -51,79 +57,377 @@ pub fn create_request(
     model: &str,
     max_tokens: u32,
     messages: Vec<ChatCompletionRequestMessage>,
-) -> Result<CreateChatCompletionRequest> {
-    let request = CreateChatCompletionRequestArgs::default()
-        .model(model)
-        .max_tokens(max_tokens)
-        .messages(messages)
-        .build()?;
-    Ok(request)
+) -> ChatRequest {
+    ChatRequest {
+        model: model.to_string(),
+        max_tokens,
+        messages,
+        reasoning: is_reasoning_model(model),
+        reasoning_effort: None,
+        sampling: Sampling::default(),
+    }
+}
+
+/// Renders a streamed response line by line and keeps the full text.
+struct ResponseRenderer<'a> {
+    buffer: ResponseBuffer,
+    reasoning_buffer: ResponseBuffer,
+    reasoning_shown: bool,
+    full_response: String,
+    highlighter: &'a mut Option<Highlighter>,
+    wrap_width: u32,
+}
+
+impl<'a> ResponseRenderer<'a> {
+    fn new(highlighter: &'a mut Option<Highlighter>, wrap_width: u32) -> Self {
+        ResponseRenderer {
+            buffer: ResponseBuffer::new(),
+            reasoning_buffer: ResponseBuffer::new(),
+            reasoning_shown: false,
+            full_response: String::new(),
+            highlighter,
+            wrap_width,
+        }
+    }
+
+    fn reasoning(&mut self, delta: &str) {
+        if !terminal_output() {
+            // keep piped output to the answer itself
+            return;
+        }
+        self.reasoning_shown = true;
+        self.reasoning_buffer.append(delta);
+        while let Some(line) = self.reasoning_buffer.get_line_with_ending() {
+            render_reasoning_line(&line, self.wrap_width);
+        }
+    }
+
+    fn text(&mut self, delta: &str) {
+        if self.reasoning_shown {
+            // separate the reasoning summary from the answer
+            if let Some(remaining) = self.reasoning_buffer.get_remaining() {
+                render_reasoning_line(&remaining, self.wrap_width);
+            }
+            print!("\n\n");
+            self.reasoning_shown = false;
+        }
+        self.buffer.append(delta);
+        self.full_response.push_str(delta);
+        while let Some(line) = self.buffer.get_line_with_ending() {
+            if let Err(e) = self.render(&line) {
+                snailprint(
+                    &format!("\n{} {}\n", "Error rendering line:".red(), e),
+                    5000,
+                );
+            }
+        }
+    }
+
+    fn render(&mut self, line: &str) -> Result<()> {
+        match self.highlighter {
+            Some(h) => render_line(line, h, self.wrap_width),
+            None => render_line_plain(line, self.wrap_width),
+        }
+    }
+
+    /// Discards the response so far, when a retry produces a different answer.
+    fn restart(&mut self) {
+        self.buffer.get_remaining();
+        self.full_response = String::new();
+        if let Some(h) = self.highlighter {
+            h.reset();
+        }
+        print_notice(&format!(
+            "\n\n{}\n\n",
+            "The retried response differs, showing it in full:".dim()
+        ));
+    }
+
+    fn finish(mut self) -> String {
+        if let Some(remaining) = self.buffer.get_remaining() {
+            let _ = self.render(&remaining);
+        }
+        self.full_response
+    }
+}
+
+/// The text of a streamed response, which may have been cut short with Ctrl-C.
+pub struct StreamedResponse {
+    pub text: String,
+    pub interrupted: bool,
+    /// Reported by the provider once the response is complete.
+    pub usage: Option<Usage>,
 }
 
+/// Streams a response, retrying transient failures. Ctrl-C stops the
+/// response (or a pending retry) and returns the text received so far.
 pub async fn stream_response(
-    client: &Client<OpenAIConfig>,
-    request: CreateChatCompletionRequest,
+    backend: &impl ChatBackend,
+    request: ChatRequest,
     highlighter: &mut Option<Highlighter>,
     wrap_width: u32,
-) -> Result<String> {
-    let mut buffer = ResponseBuffer::new();
+    retry: &RetryPolicy,
+) -> Result<StreamedResponse> {
+    let mut renderer = ResponseRenderer::new(highlighter, wrap_width);
+    let mut attempt = 0;
+    let mut interrupted = false;
+    let mut usage = None;
 
-    let mut stream = client.chat().create_stream(request).await?;
-    let mut full_response = String::new();
+    if terminal_output() {
+        println!("{}", cursor::Hide);
+    }
 
-    print!("{}\n", cursor::Hide);
+    loop {
+        let result = tokio::select! {
+            result = stream_attempt(backend, request.clone(), &mut renderer) => Some(result),
+            _ = tokio::signal::ctrl_c() => None,
+        };
+        let err = match result {
+            Some(Ok(attempt_usage)) => {
+                usage = attempt_usage;
+                break;
+            }
+            Some(Err(err)) => err,
+            None => {
+                interrupted = true;
+                break;
+            }
+        };
+        let Some(delay) = retry.delay(attempt, &err) else {
+            // the caller reports the error
+            if terminal_output() {
+                print!("{}", cursor::Show);
+            }
+            return Err(err);
+        };
+        attempt += 1;
+        print_notice(&format!("\n{} {}\n", "Warning:".yellow(), err));
+        tokio::select! {
+            _ = wait_with_countdown(delay, attempt, retry.max_retries) => {}
+            _ = tokio::signal::ctrl_c() => {
+                interrupted = true;
+                break;
+            }
+        }
+    }
+
+    let text = renderer.finish();
+    if terminal_output() {
+        if interrupted {
+            print!("\n\n{}", "[Response interrupted]".dim());
+        }
+        print!("\n{}\n", cursor::Show);
+    } else {
+        if !text.ends_with('\n') {
+            println!();
+        }
+        if interrupted {
+            eprintln!("[Response interrupted]");
+        }
+    }
+    Ok(StreamedResponse {
+        text,
+        interrupted,
+        usage,
+    })
+}
 
-    while let Some(result) = stream.next().await {
-        match result {
-            Ok(response_chunk) => {
-                if let Some(ref delta) = response_chunk.choices[0].delta.content {
-                    buffer.append(delta);
-                    full_response.push_str(delta);
+/// Streams one attempt into the renderer, returning the usage if reported.
+/// After a failed attempt, the text already shown is not printed again as
+/// long as the retry repeats it.
+async fn stream_attempt(
+    backend: &impl ChatBackend,
+    request: ChatRequest,
+    renderer: &mut ResponseRenderer<'_>,
+) -> Result<Option<Usage>> {
+    let resuming = !renderer.full_response.is_empty() || renderer.reasoning_shown;
+    let mut stream = backend.stream_chat(request).await?;
+    let mut text = String::new();
+    let mut usage: Option<Usage> = None;
+
+    while let Some(event) = stream.next().await {
+        match event? {
+            // a repeated reasoning summary would interleave with the shown answer
+            ChatEvent::Reasoning(delta) if !resuming => renderer.reasoning(&delta),
+            ChatEvent::Reasoning(_) => {}
+            ChatEvent::Text(delta) => {
+                text.push_str(&delta);
+                let shown = &renderer.full_response;
+                if text.len() <= shown.len() && shown.starts_with(&text) {
+                    // still repeating what is already on screen
+                    continue;
                 }
-                while let Some(line) = buffer.get_line_with_ending() {
-                    let result = if let Some(h) = highlighter {
-                        render_line(&line, h, wrap_width)
-                    } else {
-                        render_line_plain(&line, wrap_width)
-                    };
-                    if let Err(e) = result {
-                        snailprint(
-                            &format!("\n{} {}\n", "Error rendering line:".red(), e),
-                            5000,
-                        );
-                    }
+                if !text.starts_with(shown.as_str()) {
+                    renderer.restart();
                 }
+                let new_text = text[renderer.full_response.len()..].to_string();
+                renderer.text(&new_text);
             }
-            Err(err) => {
-                snailprint(&format!("\n{} {}\n", "Error:".red(), err), 5000);
-                return Err(err.into());
-            }
+            ChatEvent::Usage(reported) => usage.get_or_insert_default().merge(reported),
         }
     }
-    if let Some(remaining) = buffer.get_remaining() {
-        let _ = if let Some(h) = highlighter {
-            render_line(&remaining, h, wrap_width)
-        } else {
-            render_line_plain(&remaining, wrap_width)
-        };
+    Ok(usage)
+}
+
+/// Asks the model to carry out an instruction on some text, without streaming.
+/// Waits for a request that isn't streamed, failing if Ctrl-C is pressed
+/// first. Once `stream_response` has listened for Ctrl-C, it no longer ends
+/// the process, so every request has to listen for it to be cancelled.
+pub async fn interruptible<T>(request: impl Future<Output = Result<T>>) -> Result<T> {
+    tokio::select! {
+        result = request => result,
+        _ = tokio::signal::ctrl_c() => bail!("Interrupted"),
     }
+}
 
-    print!("\n{}\n", cursor::Show);
-    Ok(full_response)
+async fn complete_instruction(
+    backend: &impl ChatBackend,
+    model: &str,
+    instruction: &str,
+    text: String,
+    max_tokens: u32,
+) -> Result<String> {
+    let messages = vec![
+        new_system_message(instruction.to_string()),
+        new_user_message(text),
+    ];
+    let mut request = create_request(model, max_tokens, messages);
+    if request.reasoning {
+        // reasoning tokens count against the limit, so leave room to think
+        request.max_tokens = max_tokens.max(1024);
+        request.reasoning_effort = Some("low".to_string());
+    }
+    let answer = interruptible(backend.complete(request)).await?;
+    Ok(answer.trim().to_string())
 }
 
-pub async fn generate_title(client: &Client<OpenAIConfig>, transcript: String) -> Result<String> {
-    let prompt = format!(
-        "Generate a concise title (max 5 words) for the following conversation (to be used in a filename). Do not use any special characters.\n"
-    );
-    let messages = vec![new_system_message(prompt), new_user_message(transcript)];
-    let request = create_request("gpt-3.5-turbo", 10u32, messages)?;
+pub async fn generate_title(
+    backend: &impl ChatBackend,
+    model: &str,
+    transcript: String,
+) -> Result<String> {
+    let prompt = "Generate a concise title (max 5 words) for the following conversation (to be used in a filename). Do not use any special characters.\n";
+    let title = complete_instruction(backend, model, prompt, transcript, 10).await?;
+    if title.is_empty() {
+        Ok("Untitled Conversation".to_string())
+    } else {
+        Ok(title)
+    }
+}
+
+/// Summarizes earlier turns of a conversation so that they can be left out
+/// of requests. A previous summary is folded into the new one.
+pub async fn generate_summary(
+    backend: &impl ChatBackend,
+    model: &str,
+    previous_summary: Option<&str>,
+    transcript: String,
+) -> Result<String> {
+    let prompt = "Summarize the following conversation between a user and an assistant so that it can be continued without the original messages. Keep facts, decisions, names, code identifiers and open questions. Be concise and write in the third person.\n";
+    let text = match previous_summary {
+        Some(summary) => format!(
+            "Summary of the conversation before this:\n{}\n\n{}",
+            summary, transcript
+        ),
+        None => transcript,
+    };
+    let summary = complete_instruction(backend, model, prompt, text, 1024).await?;
+    if summary.is_empty() {
+        bail!("The model returned an empty summary");
+    }
+    Ok(summary)
+}
 
-    let response = client.chat().create(request).await?;
-    if let Some(title) = response.choices.get(0) {
-        if let Some(content) = &title.message.content {
-            return Ok(content.trim().to_string());
+/// The messages to send for a conversation after `prefix` (the system prompt
+/// and pinned files), fitted in the model's context window as the config's
+/// `context_policy` says: the oldest turns are summarized into the
+/// conversation's summary, or left out. Progress and warnings are passed to
+/// `notify`, so that the REPL and one-shot mode can each show them their way.
+pub async fn fit_to_context_window(
+    backend: &impl ChatBackend,
+    config: &Config,
+    conversation: &mut Conversation,
+    prefix: &[ChatCompletionRequestMessage],
+    model: &str,
+    max_tokens: u32,
+    notify: impl Fn(String),
+) -> Vec<ChatCompletionRequestMessage> {
+    // leave room for the response
+    let window = context_window(config, model);
+    let budget = window.saturating_sub(max_tokens) as usize;
+    if config.context_policy == ContextPolicy::Summarize
+        && count_message_tokens(model, &conversation.request_messages(prefix)) > budget
+    {
+        let summary_model = config.title_model.as_deref().unwrap_or(model);
+        summarize(
+            backend,
+            conversation,
+            prefix,
+            summary_model,
+            model,
+            budget,
+            &notify,
+        )
+        .await;
+    }
+    let mut messages = conversation.request_messages(prefix);
+    if config.context_policy != ContextPolicy::Off {
+        let dropped = truncate_to_fit(model, &mut messages, budget);
+        if dropped > 0 {
+            notify(format!(
+                "{} Left out the {} oldest messages to fit the context window of {} ({} tokens).",
+                "Warning:".yellow(),
+                dropped,
+                model.cyan(),
+                window
+            ));
         }
     }
-    Ok("Untitled Conversation".to_string())
+    messages
+}
+
+/// Summarizes the oldest turns, keeping as many recent turns as fit in half
+/// the budget so that the conversation can grow before the next summary. If
+/// summarizing fails, the turns are dropped instead.
+async fn summarize(
+    backend: &impl ChatBackend,
+    conversation: &mut Conversation,
+    prefix: &[ChatCompletionRequestMessage],
+    summary_model: &str,
+    model: &str,
+    budget: usize,
+    notify: impl Fn(String),
+) {
+    // a summary of more messages than there are doesn't belong to them
+    if conversation
+        .summary
+        .as_ref()
+        .is_some_and(|s| s.covers > conversation.messages.len())
+    {
+        conversation.summary = None;
+    }
+    let start = conversation.summary.as_ref().map_or(1, |s| s.covers);
+    let mut kept = prefix.to_vec();
+    kept.extend_from_slice(conversation.messages.get(start..).unwrap_or_default());
+    let dropped = truncate_to_fit(model, &mut kept, budget / 2);
+    if dropped == 0 {
+        return;
+    }
+    let covers = start + dropped;
+    notify(
+        format!("Summarizing {} earlier messages...", dropped)
+            .dim()
+            .to_string(),
+    );
+    let previous = conversation.summary.as_ref().map(|s| s.text.as_str());
+    let transcript =
+        format_transcript(conversation.messages.get(start..covers).unwrap_or_default());
+    match generate_summary(backend, summary_model, previous, transcript).await {
+        Ok(text) => conversation.summary = Some(Summary { text, covers }),
+        Err(e) => notify(format!(
+            "{} Failed to summarize earlier messages: {}",
+            "Warning:".yellow(),
+            e
+        )),
+    }
 }
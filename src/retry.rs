@@ -0,0 +1,109 @@
+/* -------------------------------------------------------------------------- */
+/*                                  retry.rs                                  */
+/* -------------------------------------------------------------------------- */
+
+use std::io::Write;
+use std::time::Duration;
+
+use crossterm::terminal::{Clear, ClearType};
+use yansi::Paint;
+
+use crate::backend::ApiError;
+use crate::config::Config;
+use crate::render::terminal_output;
+
+/// Upper bound for the backoff delay, not for server-requested waits.
+const MAX_BACKOFF: Duration = Duration::from_secs(60);
+
+/// Upper bound for waits requested by the server with Retry-After.
+pub const MAX_RETRY_AFTER: Duration = Duration::from_secs(600);
+
+/// Converts a number of seconds from a header or the config to a duration
+/// of at most `max`, ignoring values that aren't finite or are negative.
+pub fn seconds_to_duration(secs: f64, max: Duration) -> Option<Duration> {
+    if !secs.is_finite() || secs < 0.0 {
+        return None;
+    }
+    Some(Duration::try_from_secs_f64(secs).map_or(max, |duration| duration.min(max)))
+}
+
+/// When and how long to wait before retrying a failed request.
+#[derive(Debug, Clone)]
+pub struct RetryPolicy {
+    pub max_retries: u32,
+    pub initial_delay: Duration,
+}
+
+impl RetryPolicy {
+    pub fn from_config(config: &Config) -> Self {
+        RetryPolicy {
+            max_retries: config.max_retries,
+            initial_delay: seconds_to_duration(config.retry_delay, MAX_BACKOFF).unwrap_or_default(),
+        }
+    }
+
+    /// Returns how long to wait before the next attempt, or `None` if the
+    /// error isn't worth retrying or the retries are used up. `attempt`
+    /// counts the retries made so far.
+    pub fn delay(&self, attempt: u32, err: &anyhow::Error) -> Option<Duration> {
+        if attempt >= self.max_retries || !is_retryable(err) {
+            return None;
+        }
+        if let Some(retry_after) = err.downcast_ref::<ApiError>().and_then(|e| e.retry_after) {
+            return Some(retry_after);
+        }
+        // exponential backoff with jitter, so that clients don't retry in lockstep
+        let backoff = self
+            .initial_delay
+            .saturating_mul(2u32.saturating_pow(attempt))
+            .min(MAX_BACKOFF);
+        Some(backoff.mul_f64(0.5 + fastrand::f64() * 0.5))
+    }
+}
+
+/// Rate limits, server errors and dropped connections are transient;
+/// anything else (bad request, invalid key, unknown model) is not.
+fn is_retryable(err: &anyhow::Error) -> bool {
+    if let Some(err) = err.downcast_ref::<ApiError>() {
+        let status = err.status.as_u16();
+        return matches!(status, 408 | 409 | 429) || status >= 500;
+    }
+    if let Some(err) = err.downcast_ref::<reqwest::Error>() {
+        return err.is_timeout() || err.is_connect() || err.is_request() || err.is_body();
+    }
+    false
+}
+
+/// Waits for `delay`, counting down the remaining seconds on the current line.
+pub async fn wait_with_countdown(delay: Duration, attempt: u32, max_retries: u32) {
+    if !terminal_output() {
+        tokio::time::sleep(delay).await;
+        return;
+    }
+    let mut remaining = delay;
+    loop {
+        print!(
+            "\r{}{} Retrying in {}s (attempt {}/{})",
+            Clear(ClearType::CurrentLine),
+            "Retry:".yellow(),
+            remaining.as_secs_f64().ceil(),
+            attempt,
+            max_retries
+        );
+        let _ = std::io::stdout().flush();
+        if remaining.is_zero() {
+            break;
+        }
+        // tick on whole seconds so the countdown reads 3, 2, 1
+        let fraction = Duration::from_secs_f64(remaining.as_secs_f64().fract());
+        let step = if fraction.is_zero() {
+            Duration::from_secs(1).min(remaining)
+        } else {
+            fraction
+        };
+        tokio::time::sleep(step).await;
+        remaining = remaining.saturating_sub(step);
+    }
+    print!("\r{}", Clear(ClearType::CurrentLine));
+    let _ = std::io::stdout().flush();
+}
@@ -0,0 +1,96 @@
+/* -------------------------------------------------------------------------- */
+/*                                 session.rs                                 */
+/* -------------------------------------------------------------------------- */
+
+// Opening saved conversations by reference (`/load`, `--load`, `--continue`),
+// and the session saved with `auto_save`: the REPL records where it saved the
+// conversation, so that the next REPL can resume it with `auto_load`.
+
+use std::path::Path;
+
+use anyhow::{Context, Result, bail};
+
+use crate::config::{Config, get_session_path};
+use crate::conversation::Conversation;
+use crate::store::Store;
+
+/// Opens a saved conversation given as a file path or a stored conversation
+/// id. Returns the conversation, and the file it was read from.
+pub fn open(config: &Config, reference: &str) -> Result<(Conversation, Option<String>)> {
+    if Path::new(reference).is_file() {
+        let conversation = Conversation::from_json_file(reference)?;
+        return Ok((conversation, Some(reference.to_string())));
+    }
+    let conversation = Store::open(config)
+        .and_then(|store| store.load(reference))
+        .with_context(|| format!("No conversation file or id '{}'", reference))?;
+    Ok((conversation, None))
+}
+
+/// Opens the most recently saved conversation, from the store or the
+/// conversations folder.
+pub fn open_latest(config: &Config) -> Result<(Conversation, Option<String>)> {
+    if config.sqlite_store {
+        let store = Store::open(config)?;
+        let Some(latest) = store.recent()?.into_iter().next() else {
+            bail!("No saved conversations");
+        };
+        return Ok((store.load(&latest.id)?, None));
+    }
+    let folder = shellexpand::full(&config.conversations_folder)
+        .map_err(|e| anyhow::anyhow!("Path expansion failed: {}", e))?
+        .to_string();
+    let mut files: Vec<_> = std::fs::read_dir(&folder)
+        .with_context(|| format!("Failed to read directory {}", folder))?
+        .filter_map(|e| e.ok())
+        .map(|e| e.path())
+        .filter(|path| path.extension().is_some_and(|ext| ext == "json"))
+        .filter_map(|path| {
+            let modified = path.metadata().and_then(|m| m.modified()).ok()?;
+            Some((modified, path))
+        })
+        .collect();
+    files.sort_by_key(|(modified, _)| std::cmp::Reverse(*modified));
+    // other JSON files in the folder aren't conversations, and are skipped
+    files
+        .into_iter()
+        .find_map(|(_, path)| {
+            let path = path.to_string_lossy().to_string();
+            let conversation = Conversation::from_json_file(&path).ok()?;
+            Some((conversation, Some(path)))
+        })
+        .with_context(|| format!("No saved conversations in {}", folder))
+}
+
+/// Saves a conversation back to the file it was opened from, or to the
+/// store.
+pub fn save(config: &Config, conversation: &Conversation, file: Option<&str>) -> Result<()> {
+    match file {
+        Some(file) => conversation.save_to_json_file(file),
+        None => Store::open(config)?.save(conversation),
+    }
+}
+
+/// Records where the current session was saved: a file path, or the
+/// conversation's id in the store.
+pub fn record(reference: &str) -> Result<()> {
+    let path = get_session_path()?;
+    std::fs::write(&path, reference)
+        .with_context(|| format!("Failed to write session file {}", path.display()))
+}
+
+/// Opens the last recorded session, if any.
+pub fn open_last(config: &Config) -> Result<Option<(Conversation, Option<String>)>> {
+    let path = get_session_path()?;
+    let reference = match std::fs::read_to_string(&path) {
+        Ok(reference) => reference.trim().to_string(),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+        Err(e) => {
+            return Err(e).with_context(|| format!("Failed to read {}", path.display()));
+        }
+    };
+    if reference.is_empty() {
+        return Ok(None);
+    }
+    open(config, &reference).map(Some)
+}
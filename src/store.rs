@@ -0,0 +1,220 @@
+/* -------------------------------------------------------------------------- */
+/*                                  store.rs                                  */
+/* -------------------------------------------------------------------------- */
+
+// An optional SQLite store for saved conversations, enabled with
+// `sqlite_store`. Conversations are stored in the saved file format, with a
+// full-text index of their titles and messages for `/search` and `hey search`.
+
+use anyhow::{Context, Result, bail};
+use chrono::{DateTime, Local};
+use rusqlite::{Connection, OptionalExtension, params};
+use yansi::Paint;
+
+use crate::config::{Config, get_conversation_store_path};
+use crate::conversation::Conversation;
+use crate::render::snailprint;
+
+const SCHEMA: &str = "
+CREATE TABLE IF NOT EXISTS conversations (
+    id TEXT PRIMARY KEY,
+    title TEXT,
+    created TEXT NOT NULL,
+    updated TEXT NOT NULL,
+    model TEXT,
+    data TEXT NOT NULL
+);
+CREATE VIRTUAL TABLE IF NOT EXISTS conversations_fts USING fts5(
+    id UNINDEXED,
+    title,
+    content
+);
+";
+
+/// Maximum number of conversations listed by searches and `/load`.
+const LIST_LIMIT: usize = 50;
+
+/// Marks the start and end of matched terms in snippets.
+const MATCH_START: &str = "\u{2}";
+const MATCH_END: &str = "\u{3}";
+
+/// A stored conversation as listed by `/load` and searches.
+pub struct StoredConversation {
+    pub id: String,
+    pub title: Option<String>,
+    pub updated: DateTime<Local>,
+    /// The text around the matched terms, for search results.
+    pub snippet: Option<String>,
+}
+
+pub struct Store {
+    connection: Connection,
+}
+
+impl Store {
+    /// Opens the store, failing if it isn't enabled in the config.
+    pub fn open(config: &Config) -> Result<Self> {
+        if !config.sqlite_store {
+            bail!("The conversation store is disabled. Set `sqlite_store = true` to use it.");
+        }
+        let path = get_conversation_store_path()?;
+        let connection = Connection::open(&path)
+            .with_context(|| format!("Failed to open conversation store {}", path.display()))?;
+        connection
+            .execute_batch(SCHEMA)
+            .context("Failed to create conversation store")?;
+        Ok(Store { connection })
+    }
+
+    /// Saves a conversation, replacing an earlier save with the same id.
+    pub fn save(&mut self, conversation: &Conversation) -> Result<()> {
+        let transaction = self.connection.transaction()?;
+        transaction.execute(
+            "INSERT INTO conversations (id, title, created, updated, model, data)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6)
+             ON CONFLICT (id) DO UPDATE SET
+                title = excluded.title,
+                updated = excluded.updated,
+                model = excluded.model,
+                data = excluded.data",
+            params![
+                conversation.id,
+                conversation.title,
+                conversation.created.to_rfc3339(),
+                conversation.updated.to_rfc3339(),
+                conversation.model,
+                conversation.to_json()?,
+            ],
+        )?;
+        transaction.execute(
+            "DELETE FROM conversations_fts WHERE id = ?1",
+            [&conversation.id],
+        )?;
+        transaction.execute(
+            "INSERT INTO conversations_fts (id, title, content) VALUES (?1, ?2, ?3)",
+            params![
+                conversation.id,
+                conversation.title.as_deref().unwrap_or_default(),
+                conversation.transcript(),
+            ],
+        )?;
+        transaction.commit()?;
+        Ok(())
+    }
+
+    pub fn load(&self, id: &str) -> Result<Conversation> {
+        let data: Option<String> = self
+            .connection
+            .query_row(
+                "SELECT data FROM conversations WHERE id = ?1",
+                [id],
+                |row| row.get(0),
+            )
+            .optional()?;
+        let data = data.with_context(|| format!("No saved conversation with id '{}'", id))?;
+        Conversation::from_json(&data, Local::now())
+            .with_context(|| format!("Invalid saved conversation '{}'", id))
+    }
+
+    /// Lists the most recently updated conversations.
+    pub fn recent(&self) -> Result<Vec<StoredConversation>> {
+        let mut statement = self.connection.prepare(
+            "SELECT id, title, updated FROM conversations ORDER BY updated DESC LIMIT ?1",
+        )?;
+        let rows = statement.query_map([LIST_LIMIT], |row| {
+            Ok(StoredConversation {
+                id: row.get(0)?,
+                title: row.get(1)?,
+                updated: parse_timestamp(row.get(2)?),
+                snippet: None,
+            })
+        })?;
+        Ok(rows.collect::<rusqlite::Result<_>>()?)
+    }
+
+    /// Finds the conversations containing all of the terms (as word
+    /// prefixes), best matches first.
+    pub fn search(&self, terms: &[String]) -> Result<Vec<StoredConversation>> {
+        let query = terms
+            .iter()
+            .map(|term| format!("\"{}\"*", term.replace('"', "\"\"")))
+            .collect::<Vec<_>>()
+            .join(" ");
+        let mut statement = self.connection.prepare(
+            "SELECT c.id, c.title, c.updated,
+                    snippet(conversations_fts, -1, ?2, ?3, '…', 12)
+             FROM conversations_fts
+             JOIN conversations c ON c.id = conversations_fts.id
+             WHERE conversations_fts MATCH ?1
+             ORDER BY rank
+             LIMIT ?4",
+        )?;
+        let rows =
+            statement.query_map(params![query, MATCH_START, MATCH_END, LIST_LIMIT], |row| {
+                Ok(StoredConversation {
+                    id: row.get(0)?,
+                    title: row.get(1)?,
+                    updated: parse_timestamp(row.get(2)?),
+                    snippet: row.get(3)?,
+                })
+            })?;
+        Ok(rows.collect::<rusqlite::Result<_>>()?)
+    }
+}
+
+fn parse_timestamp(timestamp: String) -> DateTime<Local> {
+    DateTime::parse_from_rfc3339(&timestamp)
+        .map(|date| date.with_timezone(&Local))
+        .unwrap_or_default()
+}
+
+impl StoredConversation {
+    /// A one-line label for selection lists.
+    pub fn label(&self) -> String {
+        format!(
+            "{} {}",
+            self.title.as_deref().unwrap_or("Untitled Conversation"),
+            self.updated.format("(%Y-%m-%d %H:%M)").dim()
+        )
+    }
+}
+
+/// Prints numbered search results, with the matched terms highlighted.
+pub fn print_results(results: &[StoredConversation]) {
+    if results.is_empty() {
+        snailprint(
+            &format!("\n{}\n\n", "No matching conversations.".dim()),
+            1000,
+        );
+        return;
+    }
+    println!();
+    for (i, result) in results.iter().enumerate() {
+        snailprint(
+            &format!(
+                "{} {} {}\n",
+                format!("{}.", i + 1).cyan(),
+                result.label(),
+                result.id.dim()
+            ),
+            1000,
+        );
+        if let Some(snippet) = &result.snippet {
+            let snippet = snippet
+                .split_whitespace()
+                .collect::<Vec<_>>()
+                .join(" ")
+                .split(MATCH_START)
+                .enumerate()
+                .map(|(i, part)| match part.split_once(MATCH_END) {
+                    Some((matched, rest)) if i > 0 => {
+                        format!("{}{}", matched.yellow().bold(), rest)
+                    }
+                    _ => part.to_string(),
+                })
+                .collect::<String>();
+            snailprint(&format!("   {}\n", snippet), 1000);
+        }
+    }
+    println!();
+}
@@ -0,0 +1,113 @@
+/* -------------------------------------------------------------------------- */
+/*                                 tokens.rs                                  */
+/* -------------------------------------------------------------------------- */
+
+// Offline token counting, used to keep requests within the model's context
+// window. OpenAI models are counted with their own encoding; other models
+// (Claude, local models) don't publish one, so cl100k is used as an estimate.
+
+use async_openai::types::ChatCompletionRequestMessage;
+use tiktoken_rs::{CoreBPE, cl100k_base_singleton, o200k_base_singleton};
+
+use crate::utils::message_text;
+
+/// Models encoded with o200k; older OpenAI models use cl100k.
+const O200K_MODEL_PREFIXES: [&str; 6] = ["gpt-4o", "gpt-4.1", "gpt-5", "o1", "o3", "o4"];
+
+/// Tokens added per message for the role and separators.
+const TOKENS_PER_MESSAGE: usize = 3;
+/// Tokens that prime the assistant's reply.
+const REPLY_PRIMING_TOKENS: usize = 3;
+
+fn tokenizer(model: &str) -> &'static CoreBPE {
+    let name = model.rsplit('/').next().unwrap_or(model);
+    if O200K_MODEL_PREFIXES
+        .iter()
+        .any(|prefix| name.starts_with(prefix))
+    {
+        o200k_base_singleton()
+    } else {
+        cl100k_base_singleton()
+    }
+}
+
+pub fn count_tokens(model: &str, text: &str) -> usize {
+    tokenizer(model).encode_with_special_tokens(text).len()
+}
+
+pub fn count_message_tokens(model: &str, messages: &[ChatCompletionRequestMessage]) -> usize {
+    let bpe = tokenizer(model);
+    messages
+        .iter()
+        .map(|message| message_tokens(bpe, message))
+        .sum::<usize>()
+        + REPLY_PRIMING_TOKENS
+}
+
+fn message_tokens(bpe: &CoreBPE, message: &ChatCompletionRequestMessage) -> usize {
+    TOKENS_PER_MESSAGE + bpe.encode_with_special_tokens(message_text(message)).len()
+}
+
+/* -------------------------------------------------------------------------- */
+/*                               Context Policy                               */
+/* -------------------------------------------------------------------------- */
+
+/// What to do when a conversation outgrows the model's context window.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ContextPolicy {
+    /// Drop the oldest turns.
+    Truncate,
+    /// Replace the oldest turns with a summary written by the model, and
+    /// drop turns only if the summary doesn't make the conversation fit.
+    Summarize,
+    /// Send the conversation as is and let the provider reject it.
+    Off,
+}
+
+impl ContextPolicy {
+    /// Parses a `context_policy` setting.
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name.to_lowercase().as_str() {
+            "truncate" => Some(ContextPolicy::Truncate),
+            "summarize" | "summarise" => Some(ContextPolicy::Summarize),
+            "off" | "none" => Some(ContextPolicy::Off),
+            _ => None,
+        }
+    }
+}
+
+/// Drops the oldest turns until the messages fit in `budget` tokens. Leading
+/// system messages (the system prompt and pinned files) and the latest message
+/// are always kept, and whole exchanges are dropped so that the remaining
+/// messages still start with a user turn. Returns the number of messages
+/// dropped.
+pub fn truncate_to_fit(
+    model: &str,
+    messages: &mut Vec<ChatCompletionRequestMessage>,
+    budget: usize,
+) -> usize {
+    let start = messages
+        .iter()
+        .take_while(|m| matches!(m, ChatCompletionRequestMessage::System(_)))
+        .count();
+    // each message is counted once, and its tokens subtracted as it's dropped
+    let bpe = tokenizer(model);
+    let tokens: Vec<usize> = messages
+        .iter()
+        .map(|message| message_tokens(bpe, message))
+        .collect();
+    let mut total = tokens.iter().sum::<usize>() + REPLY_PRIMING_TOKENS;
+    let mut end = start;
+    while total > budget && messages.len() - end > 1 {
+        total -= tokens[end];
+        end += 1;
+        while messages.len() - end > 1
+            && !matches!(messages[end], ChatCompletionRequestMessage::User(_))
+        {
+            total -= tokens[end];
+            end += 1;
+        }
+    }
+    messages.drain(start..end);
+    end - start
+}
@@ -0,0 +1,238 @@
+/* -------------------------------------------------------------------------- */
+/*                                  usage.rs                                  */
+/* -------------------------------------------------------------------------- */
+
+// Token usage and cost of responses. Each response's usage is recorded with
+// the conversation and appended to a ledger file, which `hey usage` reports on.
+
+use std::collections::BTreeMap;
+use std::fs::{self, OpenOptions};
+use std::io::Write;
+
+use anyhow::{Context, Result};
+use chrono::{DateTime, Local, NaiveDate};
+use serde::{Deserialize, Serialize};
+use yansi::Paint;
+
+use crate::backend::Usage;
+use crate::config::{Config, get_usage_ledger_path};
+use crate::render::snailprint;
+
+/// Price of a model in USD per million tokens.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+pub struct Price {
+    pub input: f64,
+    pub output: f64,
+}
+
+/// Prices of common models, matched by the longest model name prefix.
+/// Models can be added or corrected in the `[prices]` config table.
+const PRICES: [(&str, f64, f64); 17] = [
+    ("gpt-4o", 2.5, 10.0),
+    ("gpt-4o-mini", 0.15, 0.6),
+    ("gpt-4.1", 2.0, 8.0),
+    ("gpt-4.1-mini", 0.4, 1.6),
+    ("gpt-4.1-nano", 0.1, 0.4),
+    ("gpt-5", 1.25, 10.0),
+    ("gpt-5-mini", 0.25, 2.0),
+    ("gpt-5-nano", 0.05, 0.4),
+    ("o1", 15.0, 60.0),
+    ("o3", 2.0, 8.0),
+    ("o3-mini", 1.1, 4.4),
+    ("o4-mini", 1.1, 4.4),
+    ("claude-opus-4", 15.0, 75.0),
+    ("claude-sonnet-4", 3.0, 15.0),
+    ("claude-haiku-4-5", 1.0, 5.0),
+    ("claude-3-7-sonnet", 3.0, 15.0),
+    ("claude-3-5-haiku", 0.8, 4.0),
+];
+
+/// Returns the price of a model (or alias), if known.
+pub fn price(config: &Config, model: &str) -> Option<Price> {
+    if let Some(price) = config.prices.get(model) {
+        return Some(*price);
+    }
+    let model = config.aliases.get(model).map_or(model, String::as_str);
+    if let Some(price) = config.prices.get(model) {
+        return Some(*price);
+    }
+    let name = model.rsplit('/').next().unwrap_or(model);
+    PRICES
+        .iter()
+        .filter(|(prefix, _, _)| name.starts_with(prefix))
+        .max_by_key(|(prefix, _, _)| prefix.len())
+        .map(|(_, input, output)| Price {
+            input: *input,
+            output: *output,
+        })
+}
+
+/// The usage of one response, as recorded in conversations and the ledger.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct TurnUsage {
+    pub model: String,
+    pub prompt_tokens: u32,
+    pub completion_tokens: u32,
+    /// Cost in USD, when the model's price is known.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub cost: Option<f64>,
+}
+
+impl TurnUsage {
+    pub fn new(config: &Config, model: &str, usage: Usage) -> Self {
+        let cost = price(config, model).map(|price| {
+            (usage.prompt_tokens as f64 * price.input
+                + usage.completion_tokens as f64 * price.output)
+                / 1_000_000.0
+        });
+        TurnUsage {
+            model: config
+                .aliases
+                .get(model)
+                .map_or(model, String::as_str)
+                .to_string(),
+            prompt_tokens: usage.prompt_tokens,
+            completion_tokens: usage.completion_tokens,
+            cost,
+        }
+    }
+
+    /// A one-line summary shown after a response.
+    pub fn footer(&self) -> String {
+        let mut footer = format!("{} in · {} out", self.prompt_tokens, self.completion_tokens);
+        if let Some(cost) = self.cost {
+            footer.push_str(&format!(" · {}", format_cost(cost)));
+        }
+        footer
+    }
+}
+
+fn format_cost(cost: f64) -> String {
+    if cost < 1.0 {
+        format!("${:.4}", cost)
+    } else {
+        format!("${:.2}", cost)
+    }
+}
+
+/* -------------------------------------------------------------------------- */
+/*                                   Ledger                                   */
+/* -------------------------------------------------------------------------- */
+
+/// A line of the ledger file.
+#[derive(Serialize, Deserialize)]
+struct LedgerEntry {
+    timestamp: DateTime<Local>,
+    #[serde(flatten)]
+    usage: TurnUsage,
+}
+
+/// Appends a response's usage to the ledger.
+pub fn record(usage: &TurnUsage) -> Result<()> {
+    let path = get_usage_ledger_path()?;
+    let entry = LedgerEntry {
+        timestamp: Local::now(),
+        usage: usage.clone(),
+    };
+    let mut file = OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(&path)
+        .with_context(|| format!("Failed to open usage ledger {}", path.display()))?;
+    writeln!(file, "{}", serde_json::to_string(&entry)?)?;
+    Ok(())
+}
+
+/// Prints the usage recorded in the ledger, optionally only since a date
+/// (`YYYY-MM-DD`).
+pub fn print_report(since: Option<&str>) -> Result<()> {
+    let since = since
+        .map(|date| {
+            NaiveDate::parse_from_str(date, "%Y-%m-%d")
+                .with_context(|| format!("Invalid date '{}', expected YYYY-MM-DD", date))
+        })
+        .transpose()?;
+    let path = get_usage_ledger_path()?;
+    let data = match fs::read_to_string(&path) {
+        Ok(data) => data,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => String::new(),
+        Err(e) => {
+            return Err(e).with_context(|| format!("Failed to read {}", path.display()));
+        }
+    };
+    // lines that can't be parsed (e.g. from an interrupted write) are skipped
+    let usages: Vec<TurnUsage> = data
+        .lines()
+        .filter_map(|line| serde_json::from_str::<LedgerEntry>(line).ok())
+        .filter(|entry| since.is_none_or(|since| entry.timestamp.date_naive() >= since))
+        .map(|entry| entry.usage)
+        .collect();
+    let title = match since {
+        Some(since) => format!("Usage since {}", since),
+        None => "Usage".to_string(),
+    };
+    println!();
+    print_totals(&title, &usages);
+    Ok(())
+}
+
+#[derive(Default)]
+struct Totals {
+    requests: usize,
+    prompt_tokens: u64,
+    completion_tokens: u64,
+    cost: f64,
+    /// Whether some responses had no known price.
+    unpriced: bool,
+}
+
+impl Totals {
+    fn add(&mut self, usage: &TurnUsage) {
+        self.requests += 1;
+        self.prompt_tokens += usage.prompt_tokens as u64;
+        self.completion_tokens += usage.completion_tokens as u64;
+        match usage.cost {
+            Some(cost) => self.cost += cost,
+            None => self.unpriced = true,
+        }
+    }
+
+    fn format(&self) -> String {
+        let cost = match (self.cost > 0.0, self.unpriced) {
+            (false, true) => "unpriced".to_string(),
+            (true, true) => format!("{} (some unpriced)", format_cost(self.cost)),
+            _ => format_cost(self.cost),
+        };
+        let requests = if self.requests == 1 {
+            "request"
+        } else {
+            "requests"
+        };
+        format!(
+            "{} {} · {} in · {} out · {}",
+            self.requests, requests, self.prompt_tokens, self.completion_tokens, cost
+        )
+    }
+}
+
+/// Prints usage totals per model, followed by the overall total.
+pub fn print_totals(title: &str, usages: &[TurnUsage]) {
+    snailprint(&format!("{}\n", format!("{}:", title).bold()), 1000);
+    if usages.is_empty() {
+        snailprint(&format!("{}\n\n", "No usage recorded.".dim()), 1000);
+        return;
+    }
+    let mut by_model: BTreeMap<&str, Totals> = BTreeMap::new();
+    let mut total = Totals::default();
+    for usage in usages {
+        by_model.entry(&usage.model).or_default().add(usage);
+        total.add(usage);
+    }
+    for (model, totals) in &by_model {
+        snailprint(&format!("{} {}\n", model.cyan(), totals.format()), 1000);
+    }
+    if by_model.len() > 1 {
+        snailprint(&format!("{} {}\n", "Total".bold(), total.format()), 1000);
+    }
+    println!();
+}
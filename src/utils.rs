@@ -4,11 +4,12 @@
 
 use anyhow::{Context, Result};
 use async_openai::types::{
-    ChatCompletionRequestAssistantMessageArgs, ChatCompletionRequestMessage,
-    ChatCompletionRequestSystemMessageArgs, ChatCompletionRequestUserMessageArgs,
+    ChatCompletionRequestAssistantMessageArgs, ChatCompletionRequestAssistantMessageContent,
+    ChatCompletionRequestMessage, ChatCompletionRequestSystemMessageArgs,
+    ChatCompletionRequestSystemMessageContent, ChatCompletionRequestUserMessageArgs,
+    ChatCompletionRequestUserMessageContent,
 };
 use dialoguer::{Confirm, Input, Select, theme::ColorfulTheme};
-use term_size;
 use yansi::Paint;
 
 use crate::{
@@ -70,7 +71,7 @@ pub fn select_json_file(dir_path: &str) -> Result<Option<String>> {
         .with_context(|| format!("Failed to read directory {}", dir_path))?;
     let json_files: Vec<_> = entries
         .filter_map(|e| e.ok())
-        .filter(|e| e.path().extension().map_or(false, |ext| ext == "json"))
+        .filter(|e| e.path().extension().is_some_and(|ext| ext == "json"))
         .map(|e| e.path())
         .collect();
 
@@ -107,7 +108,7 @@ pub fn select_json_file(dir_path: &str) -> Result<Option<String>> {
 pub fn select_filename(generated_title: String) -> Result<String> {
     let title = generated_title;
     let title_confirmed = Confirm::new()
-        .with_prompt(&format!(
+        .with_prompt(format!(
             "\nDo you want to save the conversation with the title: {}?",
             title.blue()
         ))
@@ -120,49 +121,84 @@ pub fn select_filename(generated_title: String) -> Result<String> {
             .with_prompt("Enter a filename or title for the conversation")
             .interact_text()?
     };
-    let name = if title.is_empty() {
+    Ok(title_to_filename(&title))
+}
+
+/// Turns a conversation title into a JSON filename. Characters that aren't
+/// allowed in filenames (or would make it a path) become underscores, and
+/// leading dots are removed so that the file isn't hidden.
+pub fn title_to_filename(title: &str) -> String {
+    let name: String = title
+        .trim()
+        .to_lowercase()
+        .replace(", ", "_")
+        .chars()
+        .filter(|c| !c.is_control())
+        .map(|c| match c {
+            ' ' | '/' | '\\' | ':' | '*' | '?' | '"' | '<' | '>' | '|' => '_',
+            c => c,
+        })
+        .collect();
+    let name = name.trim_start_matches('.');
+    let name = if name.is_empty() {
         format!(
             "conversation_{}",
             chrono::Utc::now().format("%Y%m%d_%H%M%S")
         )
     } else {
-        format!(
-            "{}",
-            title
-                .trim()
-                .to_lowercase()
-                .replace(' ', "_")
-                .replace(", ", "_")
-        )
+        name.to_string()
     };
     if name.ends_with(".json") {
-        Ok(name)
+        name
+    } else {
+        format!("{}.json", name)
+    }
+}
+
+/// Builds the user message from the command line words and any piped input.
+/// The input replaces a `{stdin}` placeholder in the message if present, and
+/// is appended to it otherwise.
+pub fn compose_message(words: &[String], stdin: Option<String>) -> String {
+    let message = words.join(" ");
+    let Some(input) = stdin else {
+        return message;
+    };
+    let input = input.trim_end();
+    if message.is_empty() {
+        input.to_string()
+    } else if message.contains("{stdin}") {
+        message.replace("{stdin}", input)
     } else {
-        Ok(format!("{}.json", name))
+        format!("{}\n\n{}", message, input)
     }
 }
 
-pub fn select_model(default: &str) -> Result<String> {
-    let models = vec![
-        "gpt-3.5-turbo",
-        "gpt-4o",
-        "gpt-4o-mini",
-        "gpt-4.1",
-        // these don't work for some reason. need to investigate later
-        // "gpt-5",
-        // "o3",
-    ];
-    let default_index = models.iter().position(|&m| m == default).unwrap_or(0);
+pub fn select_model(models: &[String], default: &str) -> Result<String> {
+    let default_index = models.iter().position(|m| m == default).unwrap_or(0);
     println!();
     let selection = Select::with_theme(&ColorfulTheme::default())
         .with_prompt("Select a model")
-        .items(&models)
+        .items(models)
         .default(default_index)
         .interact()?;
     println!();
     Ok(models[selection].to_string())
 }
 
+pub fn select_profile(profiles: &[String], current: Option<&str>) -> Result<String> {
+    let default_index = current
+        .and_then(|current| profiles.iter().position(|p| p == current))
+        .unwrap_or(0);
+    println!();
+    let selection = Select::with_theme(&ColorfulTheme::default())
+        .with_prompt("Select a profile")
+        .items(profiles)
+        .default(default_index)
+        .interact()?;
+    println!();
+    Ok(profiles[selection].to_string())
+}
+
 pub fn select_theme() -> Result<String> {
     let assets = bat::assets::HighlightingAssets::from_binary();
     let themes = assets.themes().collect::<Vec<&str>>();
@@ -228,6 +264,25 @@ pub fn new_assistant_message(content: String) -> ChatCompletionRequestMessage {
         .into()
 }
 
+/// Returns the text content of a message, or an empty string for non-text content.
+pub fn message_text(message: &ChatCompletionRequestMessage) -> &str {
+    match message {
+        ChatCompletionRequestMessage::System(msg) => match &msg.content {
+            ChatCompletionRequestSystemMessageContent::Text(content) => content,
+            _ => "",
+        },
+        ChatCompletionRequestMessage::User(msg) => match &msg.content {
+            ChatCompletionRequestUserMessageContent::Text(content) => content,
+            _ => "",
+        },
+        ChatCompletionRequestMessage::Assistant(msg) => match &msg.content {
+            Some(ChatCompletionRequestAssistantMessageContent::Text(content)) => content,
+            _ => "",
+        },
+        _ => "",
+    }
+}
+
 // fn create_nametag(name: &str) -> String {
 //     let length = name.to_string().len();
 //     let name = Paint::new(name).magenta().italic();